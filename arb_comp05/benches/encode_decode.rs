@@ -0,0 +1,79 @@
+//! Times encoding and decoding of MB-scale inputs: `cargo bench -p arb_comp05`
+//!
+//! A plain timing loop, so it runs without a benchmark framework; each figure is the best of
+//! a few runs.
+
+use arb_comp05::bpe::{Bpe, EncodeMode};
+use arb_comp05::config::BpeConfig;
+use arb_comp05::sample::Sampling;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const INPUT_LEN: usize = 4 << 20;
+const RUNS: usize = 3;
+
+/// Text-like data: random words from a small dictionary, with some random bytes mixed in.
+fn input(seed: u64) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let words = (0..500)
+        .map(|_| {
+            let len = rng.gen_range(2..10);
+            (0..len)
+                .map(|_| rng.gen_range(b'a'..=b'z'))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut data = Vec::with_capacity(INPUT_LEN);
+    while data.len() < INPUT_LEN {
+        if rng.gen_ratio(1, 20) {
+            data.extend((0..8).map(|_| rng.gen::<u8>()));
+        } else {
+            data.extend(&words[rng.gen_range(0..words.len())]);
+        }
+        data.push(b' ');
+    }
+    data.truncate(INPUT_LEN);
+    data
+}
+
+fn best_of<T>(mut f: impl FnMut() -> T) -> (Duration, T) {
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        result = Some(black_box(f()));
+        best = best.min(start.elapsed());
+    }
+    (best, result.unwrap())
+}
+
+fn report(name: &str, time: Duration) {
+    let mb_per_s = INPUT_LEN as f64 / (1 << 20) as f64 / time.as_secs_f64();
+    println!(
+        "{name:<16} {:>10.1} ms {mb_per_s:>8.1} MB/s",
+        time.as_secs_f64() * 1e3
+    );
+}
+
+fn main() {
+    let data = input(0);
+    let config = BpeConfig::new().max_vocab_size(4096);
+    let sampling = Sampling::new().memory_budget(64 << 20);
+    let bpe = Bpe::from_samples(&[&data], &config, &sampling, |_| {});
+    println!(
+        "{} MiB input, {} tokens",
+        INPUT_LEN >> 20,
+        bpe.tokens().len()
+    );
+
+    let (time, greedy) = best_of(|| bpe.encode_with(&data, EncodeMode::Greedy));
+    report("encode greedy", time);
+    let (time, _) = best_of(|| bpe.encode_with(&data, EncodeMode::Ranked));
+    report("encode ranked", time);
+    let (time, decoded) = best_of(|| bpe.decode(&greedy));
+    report("decode", time);
+    assert_eq!(decoded, data);
+}
@@ -1,76 +1,770 @@
-use crate::recode::{condense, expand, to_bytes, to_ids};
-use crate::token::{find_most_common_duplicate_id_pair, merge, Token, TokenId};
+use crate::config::{BpeConfig, Weighting};
+use crate::corpus::read_corpus;
+use crate::dot;
+use crate::error::{Error, Result};
+use crate::pair_index::PairIndex;
+use crate::parallel;
+use crate::pretokenize::{split_runs, Boundaries};
+use crate::prune::{prune_tokens, TokenRemap};
+use crate::recode::{
+    condense, condense_ranked, to_ids, try_expand, try_spans, try_to_ids, TokenSpan,
+};
+use crate::relative::{Relation, RelativeSpan};
+use crate::report::{BpeReport, InputReport, LONGEST_TOKENS};
+use crate::sample::{Sampling, TrainProgress};
+use crate::symbol::Symbol;
+use crate::token::{Token, TokenId};
+use crate::token_set::TokenSet;
+use crate::vocab::Vocab;
+use crate::vocab_file::{self, VocabFormat};
 use indexmap::IndexMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::OnceLock;
 
-pub struct Bpe {
-    ids_to_tokens: IndexMap<TokenId, Token>,
-    tokens_to_ids: IndexMap<Token, TokenId>,
+/// How [`Bpe::encode_with`] applies merges
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EncodeMode {
+    /// Apply any known merge, left to right, in repeated passes.
+    #[default]
+    Greedy,
+    /// Apply merges strictly in training order (lowest id first),
+    /// reproducing the segmentation of the training data.
+    Ranked,
 }
 
-impl Bpe {
-    fn add_id(&mut self, id: TokenId, token: Token) {
-        self.ids_to_tokens.insert(id, token);
-        self.tokens_to_ids.insert(token, id);
+/// A byte pair encoding vocabulary over symbols `S` (bytes by default; see [`Symbol`])
+pub struct Bpe<S = u8> {
+    vocab: Vocab<S>,
+    /// Map views of `vocab` for [`Bpe::ids_to_tokens`] and [`Bpe::tokens_to_ids`], made on first use.
+    ids_to_tokens: OnceLock<IndexMap<TokenId, Token<S>>>,
+    tokens_to_ids: OnceLock<IndexMap<Token<S>, TokenId>>,
+    boundaries: Boundaries,
+    min_run_len: Option<usize>,
+    /// Byte length of each training input.
+    input_lens: Vec<usize>,
+    /// Per training input, the number of times each token (by id) occurs in its final segmentation.
+    usage: Vec<Vec<usize>>,
+}
+
+impl<S: Symbol> Bpe<S> {
+    fn from_vocab(vocab: Vocab<S>, boundaries: Boundaries, min_run_len: Option<usize>) -> Self {
+        Self {
+            vocab,
+            ids_to_tokens: OnceLock::new(),
+            tokens_to_ids: OnceLock::new(),
+            boundaries,
+            min_run_len,
+            input_lens: vec![],
+            usage: vec![],
+        }
     }
 
-    pub fn ids_to_tokens(&self) -> &IndexMap<TokenId, Token> {
-        &self.ids_to_tokens
+    /// Adds a token with the next id.
+    fn add_token(&mut self, token: Token<S>) -> TokenId {
+        self.ids_to_tokens.take();
+        self.tokens_to_ids.take();
+        self.vocab.push(token)
     }
 
-    pub fn tokens_to_ids(&self) -> &IndexMap<Token, TokenId> {
-        &self.tokens_to_ids
+    /// The tokens, indexed by id.
+    pub fn tokens(&self) -> &[Token<S>] {
+        self.vocab.tokens()
     }
 
-    pub fn new(data: &[&[u8]]) -> Self {
-        let mut bpe = Self {
-            ids_to_tokens: IndexMap::new(),
-            tokens_to_ids: IndexMap::new(),
-        };
+    /// The tokens as a map, built on first use; [`Bpe::tokens`] is cheaper.
+    pub fn ids_to_tokens(&self) -> &IndexMap<TokenId, Token<S>> {
+        self.ids_to_tokens.get_or_init(|| {
+            (self.tokens().iter().enumerate())
+                .map(|(i, &token)| (TokenId(i), token))
+                .collect()
+        })
+    }
+
+    /// The ids by token, built on first use.
+    pub fn tokens_to_ids(&self) -> &IndexMap<Token<S>, TokenId> {
+        self.tokens_to_ids.get_or_init(|| {
+            (self.tokens().iter().enumerate())
+                .map(|(i, &token)| (token, TokenId(i)))
+                .collect()
+        })
+    }
+
+    /// The boundaries merges may not cross, set by [`BpeConfig::boundaries`] in training.
+    pub fn boundaries(&self) -> &Boundaries {
+        &self.boundaries
+    }
+
+    /// The shortest symbol run encoded with run tokens, set by [`BpeConfig::min_run_len`].
+    pub fn min_run_len(&self) -> Option<usize> {
+        self.min_run_len
+    }
+
+    /// Trains on inputs of any symbol type; [`Bpe::new`] and [`Bpe::with_config`] train on bytes.
+    pub fn from_symbols(data: &[&[S]], config: &BpeConfig) -> Self {
+        Self::train(data, config).0
+    }
+
+    /// Trains a `Bpe`, also returning the final segmentation of each input.
+    pub(crate) fn train(data: &[&[S]], config: &BpeConfig) -> (Self, Vec<Vec<TokenId>>) {
+        let mut bpe = Self::untrained(config);
+        let patterns = bpe.train_merges(data, config);
+        (bpe, patterns)
+    }
+
+    /// Trains on a seeded sample of windows from each input, for inputs too large to train on whole.
+    ///
+    /// Merges are learned within `config`'s limits from the windows [`Sampling::windows`] chooses.
+    /// The token counts (see [`Bpe::report`]) then come from rank-ordered encodings of the full
+    /// inputs, made in chunks no longer than the sample so peak memory stays near
+    /// `sampling.memory_budget`; segmentations may differ from `encode_ranked` at chunk edges.
+    /// `progress` is told when the sample is chosen, after each merge and after each input is encoded.
+    pub fn from_samples(
+        data: &[&[S]],
+        config: &BpeConfig,
+        sampling: &Sampling,
+        mut progress: impl FnMut(TrainProgress),
+    ) -> Self {
+        let lens = data.iter().map(|x| x.len()).collect::<Vec<_>>();
+        let windows = sampling.windows(&lens);
+        progress(TrainProgress::Sampled {
+            windows: windows.iter().map(Vec::len).sum(),
+            symbols: windows.iter().flatten().map(|window| window.len()).sum(),
+        });
+
+        let mut bpe = Self::untrained(config);
+        bpe.learn_merges(data, &windows, config, &mut progress);
+
+        let chunk_len = sampling.max_symbols();
+        let mut usage = vec![];
+        for (i, x) in data.iter().enumerate() {
+            let mut counts = vec![0; bpe.vocab.len()];
+            for start in (0..x.len()).step_by(chunk_len) {
+                let chunk = start..x.len().min(start + chunk_len);
+                let ids = (bpe.try_encode_window(x, chunk, EncodeMode::Ranked))
+                    .expect("training gives every symbol a token");
+                ids.iter().for_each(|id| counts[id.0] += 1);
+            }
+            usage.push(counts);
+            progress(TrainProgress::Encoded { inputs: i + 1 });
+        }
+        bpe.add_usage(lens, usage);
+
+        bpe
+    }
+
+    /// A vocabulary of just the symbol alphabet, to be trained with `config`.
+    fn untrained(config: &BpeConfig) -> Self {
+        let mut vocab = Vocab::new();
+        for x in S::alphabet() {
+            vocab.push(Token::Symbol(x));
+        }
+
+        let min_run_len = config.min_run_len.map(|len| len.max(2));
+        Self::from_vocab(vocab, config.boundaries.clone(), min_run_len)
+    }
+
+    /// Continues training on more data with the default limits.
+    ///
+    /// Existing `TokenId`s keep their meaning; new merges are appended after them.
+    pub fn extend(&mut self, data: &[&[S]]) {
+        self.extend_with_config(data, &BpeConfig::default());
+    }
+
+    /// Continues training on more data, within `config`'s limits.
+    ///
+    /// `max_vocab_size` limits the whole vocabulary and `max_merges` the merges added by this call.
+    /// `config.boundaries` and `config.min_run_len` are ignored: the vocabulary keeps the ones
+    /// it was trained with.
+    /// Token counts for earlier training inputs are not updated.
+    pub fn extend_with_config(&mut self, data: &[&[S]], config: &BpeConfig) {
+        self.train_merges(data, config);
+    }
+
+    /// Removes merges that no training input uses in its final segmentation, and compacts ids.
+    ///
+    /// Symbol tokens are always kept. A kept merge whose parts are unused is re-split into
+    /// other remaining tokens where possible; otherwise its parts stay, as every merge is built
+    /// from exactly two tokens.
+    /// Encodings of new data may differ after pruning; the returned [`TokenRemap`] translates
+    /// ids encoded before it.
+    /// A vocabulary that was loaded rather than trained has no token counts and is left as is.
+    pub fn prune(&mut self) -> TokenRemap {
+        if self.usage.is_empty() {
+            return TokenRemap::identity(self.vocab.len());
+        }
+
+        let used = |id: TokenId| self.usage.iter().any(|counts| counts[id.0] > 0);
+        let (tokens, remap) = prune_tokens(self.tokens(), used);
+
+        let usage = (self.usage.iter())
+            .map(|old_counts| {
+                let mut counts = vec![0; tokens.len()];
+                for (old, &count) in old_counts.iter().enumerate() {
+                    for id in remap.get(TokenId(old)).unwrap() {
+                        counts[id.0] += count;
+                    }
+                }
+                counts
+            })
+            .collect();
 
-        (0..=u8::MAX).for_each(|x| bpe.add_id(TokenId(x as usize), Token::Byte(x)));
+        let mut pruned = Self::from_tokens(tokens, self.boundaries.clone(), self.min_run_len);
+        pruned.input_lens = std::mem::take(&mut self.input_lens);
+        pruned.usage = usage;
+        *self = pruned;
 
-        let mut patterns = data.iter().map(|x| bpe.encode(x)).collect::<Vec<_>>();
+        remap
+    }
+
+    /// Adds merges learned from `data` to the vocabulary, returning the final segmentation of each input.
+    fn train_merges(&mut self, data: &[&[S]], config: &BpeConfig) -> Vec<Vec<TokenId>> {
+        let windows = (data.iter())
+            .map(|x| std::iter::once(0..x.len()).collect())
+            .collect::<Vec<_>>();
+        let patterns = self.learn_merges(data, &windows, config, &mut |_| {});
+
+        let vocab_size = self.vocab.len();
+        let usage = (patterns.iter())
+            .map(|pattern| {
+                let mut counts = vec![0; vocab_size];
+                pattern.iter().for_each(|id| counts[id.0] += 1);
+                counts
+            })
+            .collect();
+        self.add_usage(data.iter().map(|x| x.len()), usage);
+
+        patterns
+    }
 
-        while let Some(((id0, id1), _count)) = find_most_common_duplicate_id_pair(patterns.iter()) {
-            let new_id = bpe.ids_to_tokens.len();
-            bpe.add_id(TokenId(new_id), Token::Merge(id0, id1));
+    /// Adds merges learned from `windows[i]` (sorted ranges) of each input `data[i]`,
+    /// returning the final segmentation of each input's windows, concatenated.
+    ///
+    /// Symbols of the whole inputs not in the vocabulary yet get ids first, in ascending order,
+    /// then runs without a run token yet that occur at least `config.min_pair_frequency` times,
+    /// in at least `config.min_inputs` inputs (as far as `config` allows more tokens).
+    /// Inputs start from their rank-ordered encoding with the current merges, which is
+    /// how they would have been segmented if they had been part of the earlier training.
+    fn learn_merges(
+        &mut self,
+        data: &[&[S]],
+        windows: &[Vec<Range<usize>>],
+        config: &BpeConfig,
+        progress: &mut dyn FnMut(TrainProgress),
+    ) -> Vec<Vec<TokenId>> {
+        let new_symbols = (data.iter().copied().flatten())
+            .filter(|&&x| self.vocab.symbol(x).is_none())
+            .copied()
+            .collect::<BTreeSet<_>>();
+        for x in new_symbols {
+            self.add_token(Token::Symbol(x));
+        }
+
+        let pieces = (data.iter().zip(windows))
+            .map(|(x, windows)| {
+                (windows.iter())
+                    .flat_map(|window| self.pieces(x, window.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // occurrences of each run without a run token yet, and the distinct inputs it occurs in
+        let mut run_counts = BTreeMap::<(TokenId, usize), (usize, usize)>::new();
+        for (x, pieces) in data.iter().zip(&pieces) {
+            let mut runs = (pieces.iter())
+                .filter(|(_, is_run)| *is_run)
+                .map(|(range, _)| {
+                    let symbol = self.vocab.symbol(x[range.start]).unwrap();
+                    (symbol, range.len())
+                })
+                .filter(|run| !self.vocab.runs().contains_key(run))
+                .collect::<Vec<_>>();
+            runs.sort();
+            for (i, &run) in runs.iter().enumerate() {
+                let (count, inputs) = run_counts.entry(run).or_default();
+                *count += 1;
+                if i == 0 || runs[i - 1] != run {
+                    *inputs += 1;
+                }
+            }
+        }
+        let new_runs = (run_counts.into_iter())
+            .filter(|&((_, len), (count, inputs))| {
+                count >= config.min_pair_frequency
+                    && inputs >= config.min_inputs
+                    && config.allows_token_len(len)
+                    && len <= vocab_file::MAX_RUN_LEN
+            })
+            .map(|(run, _)| run);
+        for (symbol, len) in new_runs {
+            if !config.allows_more_tokens(self.vocab.len(), 0) {
+                break;
+            }
+            self.add_token(Token::Run(symbol, len));
+        }
 
-            let merge_if = |current_id, next_id| {
-                if current_id == id0 && next_id == id1 {
-                    Some(TokenId(new_id))
+        let merge_if = |id0, id1| self.vocab.merge(id0, id1);
+
+        // each piece between boundaries and runs is trained as a separate pattern; runs are
+        // left empty so no pairs are counted across or within them, and encoded at the end
+        let mut segment_counts = vec![];
+        let mut input_of = vec![];
+        let mut runs = vec![];
+        let mut patterns = vec![];
+        for (i, (x, pieces)) in data.iter().zip(pieces).enumerate() {
+            segment_counts.push(pieces.len());
+            input_of.resize(input_of.len() + pieces.len(), i);
+            for (range, is_run) in pieces {
+                let ids = to_ids(&x[range], &self.vocab);
+                if is_run {
+                    runs.push(Some((ids[0], ids.len())));
+                    patterns.push(vec![]);
                 } else {
-                    None
+                    runs.push(None);
+                    patterns.push(condense_ranked(ids, merge_if));
                 }
+            }
+        }
+        let lens = (windows.iter())
+            .map(|windows| windows.iter().map(|window| window.len()).sum())
+            .collect::<Vec<_>>();
+        let input_weights = config.weighting.input_weights(&lens);
+        let weights = input_of.iter().map(|&i| input_weights[i]).collect();
+        let mut index = PairIndex::new(patterns, weights);
+
+        let mut lengths = self.token_lengths();
+        let mut merges = 0;
+
+        while config.allows_more_tokens(self.vocab.len(), merges) {
+            let Some(((id0, id1), count)) = index.pop_most_common() else {
+                break;
             };
+            if count < config.min_pair_frequency {
+                // weighted, a less common pair may still occur often enough
+                if config.weighting == Weighting::Occurrences {
+                    break;
+                }
+                continue;
+            }
 
-            patterns = patterns
-                .iter()
-                .map(|pattern| merge(pattern.iter().copied(), merge_if))
-                .collect();
+            if let Some(id) = self.vocab.merge(id0, id1) {
+                index.merge((id0, id1), id);
+                continue;
+            }
+            if config.min_inputs > 1
+                && index.count_inputs((id0, id1), &input_of) < config.min_inputs
+            {
+                continue;
+            }
+
+            let len = lengths[id0.0] + lengths[id1.0];
+            if !config.allows_token_len(len) {
+                continue;
+            }
+
+            let new_id = self.add_token(Token::Merge(id0, id1));
+            index.merge((id0, id1), new_id);
+
+            lengths.push(len);
+            merges += 1;
+            progress(TrainProgress::Merged { merges });
         }
 
-        bpe
+        let segments = index.into_patterns().into_iter().zip(runs);
+        let mut segments = segments.map(|(ids, run)| match run {
+            Some((symbol, len)) => self.encode_run(symbol, len, EncodeMode::Ranked),
+            None => ids,
+        });
+        let patterns = segment_counts
+            .into_iter()
+            .map(|count| segments.by_ref().take(count).flatten().collect())
+            .collect::<Vec<Vec<TokenId>>>();
+
+        patterns
+    }
+
+    /// Records the lengths and token counts of more training inputs.
+    fn add_usage(&mut self, lens: impl IntoIterator<Item = usize>, usage: Vec<Vec<usize>>) {
+        let vocab_size = self.vocab.len();
+        for counts in &mut self.usage {
+            counts.resize(vocab_size, 0);
+        }
+        self.input_lens.extend(lens);
+        self.usage.extend(usage);
+    }
+
+    /// Byte length of each token, by id.
+    fn token_lengths(&self) -> Vec<usize> {
+        // ids are dense and merges only refer to earlier ids
+        let mut lengths = Vec::<usize>::with_capacity(self.vocab.len());
+        for token in self.tokens() {
+            lengths.push(match *token {
+                Token::Symbol(_) => 1,
+                Token::Merge(id0, id1) => lengths[id0.0] + lengths[id1.0],
+                Token::Run(_, len) => len,
+            });
+        }
+        lengths
+    }
+
+    /// The pieces `window` of an input is encoded in: the segments between boundaries,
+    /// split around runs (marked `true`) if run tokens are enabled.
+    fn pieces(&self, data: &[S], window: Range<usize>) -> Vec<(Range<usize>, bool)> {
+        let segments = self.boundaries.segments_in(data, window).into_iter();
+        match self.min_run_len {
+            Some(min_len) => segments
+                .flat_map(|range| split_runs(data, range, min_len))
+                .collect(),
+            None => segments.map(|range| (range, false)).collect(),
+        }
+    }
+
+    /// Encodes `len` copies of the symbol token `symbol`: as one run token if there is one,
+    /// otherwise with the longest shorter run tokens and pairwise merges for the rest.
+    fn encode_run(&self, symbol: TokenId, mut len: usize, mode: EncodeMode) -> Vec<TokenId> {
+        let mut ids = vec![];
+        while let Some((&(_, run_len), &run)) = self
+            .vocab
+            .runs()
+            .range((symbol, 0)..=(symbol, len))
+            .next_back()
+        {
+            ids.resize(ids.len() + len / run_len, run);
+            len %= run_len;
+        }
+
+        let merge_if = |id0, id1| self.vocab.merge(id0, id1);
+        let rest = vec![symbol; len];
+        ids.extend(match mode {
+            EncodeMode::Greedy => condense(rest, merge_if),
+            EncodeMode::Ranked => condense_ranked(rest, merge_if),
+        });
+        ids
+    }
+
+    /// Builds a `Bpe` from a token list in id order.
+    ///
+    /// The list is expected to have been checked by `vocab_file`.
+    pub(crate) fn from_tokens(
+        tokens: Vec<Token<S>>,
+        boundaries: Boundaries,
+        min_run_len: Option<usize>,
+    ) -> Self {
+        let mut vocab = Vocab::new();
+        for token in tokens {
+            vocab.push(token);
+        }
+        Self::from_vocab(vocab, boundaries, min_run_len)
     }
 
-    pub fn encode(&self, data: &[u8]) -> Vec<TokenId> {
-        let pattern = to_ids(data, &self.tokens_to_ids);
-        let merge_if = |id0, id1| self.tokens_to_ids.get(&Token::Merge(id0, id1)).copied();
+    /// The symbols a token expands to, or `None` for an unknown id.
+    ///
+    /// A token is expanded on its first use and its expansion kept.
+    pub fn token_symbols(&self, id: TokenId) -> Option<&[S]> {
+        self.vocab.expansion(id)
+    }
+
+    /// The number of symbols a token expands to, or `None` for an unknown id.
+    ///
+    /// This doesn't expand the token; lengths too large for a `usize` are `usize::MAX`.
+    pub fn token_len(&self, id: TokenId) -> Option<usize> {
+        self.vocab.expansion_len(id)
+    }
+
+    /// The height of a token's merge tree: 0 for a symbol, 1 for a merge of two symbols
+    /// or a run, etc.
+    pub fn merge_depth(&self, id: TokenId) -> Option<usize> {
+        let mut depth = 0;
+        let mut stack = vec![(id, 0)];
+
+        while let Some((id, d)) = stack.pop() {
+            match self.vocab.get(id)? {
+                Token::Symbol(_) => depth = depth.max(d),
+                Token::Merge(id0, id1) => stack.extend([(id0, d + 1), (id1, d + 1)]),
+                Token::Run(id, _) => stack.push((id, d + 1)),
+            }
+        }
+        Some(depth)
+    }
+
+    /// How often a token occurs in the final segmentation of each training input.
+    ///
+    /// Empty for a vocabulary that was loaded rather than trained.
+    pub fn token_counts(&self, id: TokenId) -> Option<Vec<usize>> {
+        (id.0 < self.vocab.len()).then(|| self.usage.iter().map(|counts| counts[id.0]).collect())
+    }
+
+    /// Writes the vocabulary to a file (see [`crate::vocab_file`] for the formats).
+    pub fn save(&self, path: impl AsRef<Path>, format: VocabFormat) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path).map_err(Error::file(path))?;
+        self.write_to(BufWriter::new(file), format)
+            .map_err(|e| e.with_path(path))
+    }
+
+    /// Reads a vocabulary written by [`Bpe::save`], in either format.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(Error::file(path))?;
+        Self::read_from(BufReader::new(file)).map_err(|e| e.with_path(path))
+    }
+
+    pub fn write_to(&self, writer: impl Write, format: VocabFormat) -> Result<()> {
+        vocab_file::write(self, writer, format)
+    }
+
+    pub fn read_from(reader: impl Read) -> Result<Self> {
+        vocab_file::read(reader)
+    }
+
+    pub fn encode(&self, data: &[S]) -> Vec<TokenId> {
+        self.encode_with(data, EncodeMode::Greedy)
+    }
+
+    pub fn encode_ranked(&self, data: &[S]) -> Vec<TokenId> {
+        self.encode_with(data, EncodeMode::Ranked)
+    }
+
+    pub fn encode_with(&self, data: &[S], mode: EncodeMode) -> Vec<TokenId> {
+        self.try_encode_with(data, mode)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Encodes several inputs, in parallel with the `parallel` feature.
+    pub fn encode_all(&self, data: &[&[S]], mode: EncodeMode) -> Vec<Vec<TokenId>> {
+        parallel::map(data.to_vec(), |x| self.encode_with(x, mode))
+    }
+
+    pub fn try_encode(&self, data: &[S]) -> Result<Vec<TokenId>> {
+        self.try_encode_with(data, EncodeMode::Greedy)
+    }
+
+    pub fn try_encode_with(&self, data: &[S], mode: EncodeMode) -> Result<Vec<TokenId>> {
+        self.try_encode_window(data, 0..data.len(), mode)
+    }
+
+    /// Encodes `data`, pairing each token with the range of `data` it covers.
+    pub fn encode_with_spans(&self, data: &[S], mode: EncodeMode) -> Vec<TokenSpan> {
+        self.try_encode_with_spans(data, mode)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn try_encode_with_spans(&self, data: &[S], mode: EncodeMode) -> Result<Vec<TokenSpan>> {
+        try_spans(&self.try_encode_with(data, mode)?, &self.vocab)
+    }
+
+    /// Encodes `window` of `data`, with boundaries placed as in the whole of `data`.
+    fn try_encode_window(
+        &self,
+        data: &[S],
+        window: Range<usize>,
+        mode: EncodeMode,
+    ) -> Result<Vec<TokenId>> {
+        let merge_if = |id0, id1| self.vocab.merge(id0, id1);
+
+        let segments = parallel::map(self.pieces(data, window), |(range, is_run)| {
+            let pattern = try_to_ids(&data[range], &self.vocab)?;
+
+            Ok(match mode {
+                _ if is_run => self.encode_run(pattern[0], pattern.len(), mode),
+                EncodeMode::Greedy => condense(pattern, merge_if),
+                EncodeMode::Ranked => condense_ranked(pattern, merge_if),
+            })
+        });
+
+        Ok(segments.into_iter().collect::<Result<Vec<_>>>()?.concat())
+    }
 
-        condense(pattern, merge_if)
+    /// The symbols of `ids`, concatenated.
+    ///
+    /// Tokens are copied from the expansions kept by [`Bpe::token_symbols`].
+    pub fn decode(&self, ids: &[TokenId]) -> Vec<S> {
+        self.try_decode(ids).unwrap_or_else(|e| panic!("{e}"))
     }
 
-    pub fn decode(&self, data: Vec<TokenId>) -> Vec<u8> {
-        let mut result = data;
+    pub fn try_decode(&self, ids: &[TokenId]) -> Result<Vec<S>> {
+        let mut result = Vec::with_capacity(ids.len());
+        for &id in ids {
+            let symbols = self.token_symbols(id).ok_or(Error::UnknownTokenId(id))?;
+            result.extend_from_slice(symbols);
+        }
+        Ok(result)
+    }
+
+    /// Re-tokenizes `pattern` so each token is wholly shared with `reference` or wholly novel.
+    ///
+    /// `pattern` is expanded to symbols and merged again, but only where both halves are
+    /// in or both are out of the tokens reachable from `reference`. Long runs get their run
+    /// tokens where those have the same relation as their symbol. Consecutive tokens
+    /// with the same relation to `reference` make up one span.
+    pub fn retokenize_relative_to(
+        &self,
+        pattern: &[TokenId],
+        reference: &[TokenId],
+    ) -> Vec<RelativeSpan> {
+        self.try_retokenize_relative_to(pattern, reference)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
 
-        result = expand(result, &self.ids_to_tokens);
+    pub fn try_retokenize_relative_to(
+        &self,
+        pattern: &[TokenId],
+        reference: &[TokenId],
+    ) -> Result<Vec<RelativeSpan>> {
+        let reference = TokenSet::try_reachable(&[reference], &self.vocab, &self.vocab)?;
+        self.try_retokenize_within(pattern, &reference)
+    }
 
-        to_bytes(&result, &self.ids_to_tokens)
+    /// As [`Bpe::retokenize_relative_to`], against a set of tokens such as the tokens of
+    /// several inputs combined.
+    pub fn retokenize_within(
+        &self,
+        pattern: &[TokenId],
+        reference: &TokenSet,
+    ) -> Vec<RelativeSpan> {
+        self.try_retokenize_within(pattern, reference)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn try_retokenize_within(
+        &self,
+        pattern: &[TokenId],
+        reference: &TokenSet,
+    ) -> Result<Vec<RelativeSpan>> {
+        let merge_if = |id0, id1| {
+            (self.vocab.merge(id0, id1))
+                .filter(|_| reference.contains(&id0) == reference.contains(&id1))
+        };
+        let expanded = try_expand(pattern.to_vec(), &self.vocab)?;
+        let min_run_len = self.min_run_len.unwrap_or(usize::MAX);
+
+        let mut ids = vec![];
+        for (range, is_run) in split_runs(&expanded, 0..expanded.len(), min_run_len) {
+            if is_run {
+                let symbol = expanded[range.start];
+                // runs only get run tokens with the same relation as their symbol
+                let run = self.encode_run(symbol, range.len(), EncodeMode::Greedy);
+                let relation = reference.contains(&symbol);
+                if run.iter().all(|id| reference.contains(id) == relation) {
+                    ids.extend(run);
+                    continue;
+                }
+            }
+            ids.extend(condense(expanded[range].to_vec(), merge_if));
+        }
+
+        let mut spans: Vec<RelativeSpan> = vec![];
+        let mut offset = 0;
+        for id in ids {
+            let relation = if reference.contains(&id) {
+                Relation::Shared
+            } else {
+                Relation::Novel
+            };
+            let len = self.token_len(id).ok_or(Error::UnknownTokenId(id))?;
+
+            match spans.last_mut() {
+                Some(span) if span.relation == relation => {
+                    span.ids.push(id);
+                    span.range.end += len;
+                }
+                _ => spans.push(RelativeSpan {
+                    relation,
+                    ids: vec![id],
+                    range: offset..offset + len,
+                }),
+            }
+            offset += len;
+        }
+        Ok(spans)
+    }
+}
+
+impl Bpe {
+    pub fn new(data: &[&[u8]]) -> Self {
+        Self::with_config(data, &BpeConfig::default())
+    }
+
+    pub fn with_config(data: &[&[u8]], config: &BpeConfig) -> Self {
+        Self::from_symbols(data, config)
+    }
+
+    /// Trains on every file under `paths` (files or directories; see [`read_corpus`]).
+    ///
+    /// The result can encode any input: bytes not covered by a merge stay byte tokens.
+    pub fn from_corpus(paths: &[impl AsRef<Path>], config: &BpeConfig) -> Result<Self> {
+        let corpus = read_corpus(paths)?;
+        let corpus = corpus.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        Ok(Self::with_config(&corpus, config))
+    }
+
+    /// The bytes a token expands to, or `None` for an unknown id.
+    pub fn token_bytes(&self, id: TokenId) -> Option<&[u8]> {
+        self.token_symbols(id)
+    }
+
+    /// Writes the bytes of `ids` to `writer`, without collecting them first.
+    ///
+    /// Each token is a separate write, so a `File` or socket should be wrapped in a `BufWriter`.
+    pub fn decode_into(&self, ids: &[TokenId], mut writer: impl Write) -> Result<()> {
+        for &id in ids {
+            writer.write_all(self.token_bytes(id).ok_or(Error::UnknownTokenId(id))?)?;
+        }
+        Ok(())
+    }
+
+    /// Summarizes the vocabulary and how it segmented the training inputs.
+    pub fn report(&self) -> BpeReport {
+        let lengths = self.token_lengths();
+        let mut depths = Vec::<usize>::with_capacity(self.vocab.len());
+        for token in self.tokens() {
+            depths.push(match *token {
+                Token::Symbol(_) => 0,
+                Token::Merge(id0, id1) => depths[id0.0].max(depths[id1.0]) + 1,
+                Token::Run(id, _) => depths[id.0] + 1,
+            });
+        }
+
+        let mut longest = (0..self.vocab.len()).map(TokenId).collect::<Vec<_>>();
+        longest.sort_by_key(|id| (std::cmp::Reverse(lengths[id.0]), *id));
+        longest.truncate(LONGEST_TOKENS);
+
+        BpeReport {
+            vocab_size: self.vocab.len(),
+            merges: (self.tokens().iter())
+                .filter(|token| matches!(token, Token::Merge(..)))
+                .count(),
+            max_merge_depth: depths.iter().copied().max().unwrap_or(0),
+            longest_tokens: longest
+                .into_iter()
+                .map(|id| (id, self.token_bytes(id).unwrap().to_vec()))
+                .collect(),
+            inputs: self
+                .input_lens
+                .iter()
+                .zip(&self.usage)
+                .map(|(&bytes, counts)| InputReport {
+                    bytes,
+                    tokens: counts.iter().sum(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Renders the merge tree below `roots` (all merges if `None`) as Graphviz DOT;
+    /// see [`dot::to_dot`].
+    pub fn to_dot(&self, roots: Option<&[TokenId]>) -> Result<String> {
+        dot::to_dot(self, roots)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::matcher::{greedy00, Matched};
+    use crate::sample::BYTES_PER_SYMBOL;
+    use rand::{Rng, SeedableRng};
 
     #[test]
     fn test_bpe() {
@@ -80,7 +774,7 @@ mod tests {
             vec![TokenId(0x61), TokenId(0x62), TokenId(0x63)]
         );
         assert_eq!(
-            bpe.decode(vec![TokenId(0x61), TokenId(0x62), TokenId(0x63)]),
+            bpe.decode(&[TokenId(0x61), TokenId(0x62), TokenId(0x63)]),
             vec![0x61, 0x62, 0x63]
         );
 
@@ -90,7 +784,7 @@ mod tests {
             vec![TokenId(0x61), TokenId(0x62), TokenId(0x63)]
         );
         assert_eq!(
-            bpe.decode(vec![TokenId(0x61), TokenId(0x62), TokenId(0x63)]),
+            bpe.decode(&[TokenId(0x61), TokenId(0x62), TokenId(0x63)]),
             vec![0x61, 0x62, 0x63]
         );
 
@@ -100,7 +794,7 @@ mod tests {
             &[0x61, 0x62, 0x63],
         ]);
         assert_eq!(bpe.encode(&[0x61, 0x62, 0x63]), vec![TokenId(257)]);
-        assert_eq!(bpe.decode(vec![TokenId(257)]), vec![0x61, 0x62, 0x63]);
+        assert_eq!(bpe.decode(&[TokenId(257)]), vec![0x61, 0x62, 0x63]);
 
         let bpe = Bpe::new(&[&[1, 2, 3, 2, 3, 4], &[1, 2, 3, 1, 2, 3]]);
         assert_eq!(
@@ -108,8 +802,560 @@ mod tests {
             vec![TokenId(257), TokenId(256), TokenId(4)]
         );
         assert_eq!(
-            bpe.decode(vec![TokenId(257), TokenId(256), TokenId(4)]),
+            bpe.decode(&[TokenId(257), TokenId(256), TokenId(4)]),
             vec![1, 2, 3, 2, 3, 4]
         );
     }
+
+    #[test]
+    fn test_try_decode() {
+        let bpe = Bpe::new(&[&[1, 2, 1, 2]]);
+        assert_eq!(
+            bpe.try_encode(&[1, 2, 3]).unwrap(),
+            vec![TokenId(256), TokenId(3)]
+        );
+        assert_eq!(
+            bpe.try_decode(&[TokenId(256), TokenId(3)]).unwrap(),
+            vec![1, 2, 3]
+        );
+        assert!(matches!(
+            bpe.try_decode(&[TokenId(257)]),
+            Err(Error::UnknownTokenId(TokenId(257)))
+        ));
+    }
+
+    #[test]
+    fn test_decode_into() {
+        let data = b"abcabc abcabc";
+        let mut bpe = Bpe::new(&[data]);
+        let ids = bpe.encode(data);
+
+        let mut out = vec![];
+        bpe.decode_into(&ids, &mut out).unwrap();
+        assert_eq!(out, data);
+        assert_eq!(bpe.decode(&ids), data);
+        let bytes = ids.iter().map(|&id| bpe.token_bytes(id).unwrap());
+        assert_eq!(bytes.collect::<Vec<_>>().concat(), data);
+        assert!(matches!(
+            bpe.decode_into(&[TokenId(0), TokenId(999)], &mut out),
+            Err(Error::UnknownTokenId(TokenId(999)))
+        ));
+
+        // tokens added after other tokens were expanded are expanded too
+        bpe.extend(&[b"xyxy"]);
+        let xy = bpe.tokens_to_ids()[&Token::Merge(TokenId(b'x' as usize), TokenId(b'y' as usize))];
+        assert_eq!(bpe.token_bytes(xy), Some(&b"xy"[..]));
+        assert_eq!(bpe.decode(&bpe.encode(b"abcxy")), b"abcxy");
+    }
+
+    #[test]
+    fn test_bpe_config() {
+        let data: &[&[u8]] = &[&[1, 2, 3, 4, 1, 2, 3, 4], &[1, 2, 3, 4, 5, 5]];
+        let vocab_size = |config: &BpeConfig| Bpe::with_config(data, config).ids_to_tokens().len();
+
+        assert_eq!(
+            Bpe::with_config(data, &BpeConfig::default()).ids_to_tokens(),
+            Bpe::new(data).ids_to_tokens()
+        );
+        assert_eq!(vocab_size(&BpeConfig::new()), 259);
+
+        assert_eq!(vocab_size(&BpeConfig::new().max_vocab_size(258)), 258);
+        assert_eq!(vocab_size(&BpeConfig::new().max_vocab_size(100)), 256);
+        assert_eq!(vocab_size(&BpeConfig::new().max_merges(1)), 257);
+        assert_eq!(vocab_size(&BpeConfig::new().min_pair_frequency(4)), 256);
+
+        // (1 2 3 4) needs a 4 byte token
+        let bpe = Bpe::with_config(data, &BpeConfig::new().max_token_len(3));
+        assert_eq!(bpe.ids_to_tokens().len(), 258);
+        assert_eq!(bpe.encode(&[1, 2, 3, 4]).len(), 2);
+        assert_eq!(bpe.decode(&bpe.encode(&[1, 2, 3, 4])), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_min_inputs() {
+        let a = b"xyxyxyxy the shared part 0000000000";
+        let b = b"the shared part zwzwzwzw 0000000000";
+        let c = b"zwzw";
+        let contains = |x: &[u8], part: &[u8]| x.windows(part.len()).any(|w| w == part);
+
+        // xy and zw only repeat within one input
+        let bpe = Bpe::new(&[a, b]);
+        assert!(bpe.tokens_to_ids().contains_key(&Token::Merge(
+            TokenId(b'x' as usize),
+            TokenId(b'y' as usize)
+        )));
+
+        let config = BpeConfig::new().min_inputs(2).min_run_len(4);
+        let shared = Bpe::with_config(&[a, b], &config);
+        assert!(shared.ids_to_tokens().len() > 257);
+        for &id in shared.ids_to_tokens().keys().skip(256) {
+            let bytes = shared.token_bytes(id).unwrap();
+            assert!(contains(a, bytes) && contains(b, bytes), "{bytes:?}");
+        }
+        assert_eq!(shared.encode(b"the shared part ").len(), 1);
+        assert_eq!(shared.encode(b"xyxyxyxy").len(), 8);
+
+        // the differing blocks keep their bytes
+        let matches = greedy00(&shared.encode(a), &shared.encode(b));
+        assert!(matches.iter().any(|m| matches!(m, Matched::Same(_))));
+
+        // the 10 byte run is shared, but not by all three inputs
+        let run = Token::Run(TokenId(b'0' as usize), 10);
+        assert!(shared.tokens_to_ids().contains_key(&run));
+        let three = Bpe::with_config(&[a, b, c], &config.min_inputs(3));
+        assert_eq!(three.ids_to_tokens().len(), 256);
+    }
+
+    #[test]
+    fn test_weighting() {
+        let image = b"abcdefgh".repeat(250);
+        let blob = b"kv;kv;kv;kv";
+        let contains = |bpe: &Bpe, a, b| {
+            (bpe.tokens_to_ids())
+                .contains_key(&Token::Merge(TokenId(a as usize), TokenId(b as usize)))
+        };
+
+        // the image's pairs occur far more often
+        let config = BpeConfig::new().max_merges(1);
+        let bpe = Bpe::with_config(&[&image, blob], &config);
+        assert!(!contains(&bpe, b'k', b'v'));
+
+        // but the blob has more structure for its size
+        let bpe = Bpe::with_config(
+            &[&image, blob],
+            &config.clone().weighting(Weighting::PerInput),
+        );
+        assert!(contains(&bpe, b'k', b'v'));
+        assert_eq!(bpe.encode(blob).len(), 7);
+
+        let custom = |weights: Vec<u64>| config.clone().weighting(Weighting::Custom(weights));
+        let bpe = Bpe::with_config(&[&image, blob], &custom(vec![1, 100]));
+        assert!(contains(&bpe, b'k', b'v'));
+        let bpe = Bpe::with_config(&[&image, blob], &custom(vec![1]));
+        assert!(!contains(&bpe, b'k', b'v'));
+
+        // min_pair_frequency still counts occurrences, whatever their weight
+        let config = BpeConfig::new()
+            .min_pair_frequency(5)
+            .weighting(Weighting::Custom(vec![1, 1000]));
+        let bpe = Bpe::with_config(&[&image, blob], &config);
+        assert!(!contains(&bpe, b'k', b'v'));
+        assert!(bpe.encode(&image).len() < 50);
+
+        assert_eq!(
+            Weighting::PerInput.input_weights(&[1000, 10, 0]),
+            vec![1, 100, 1000]
+        );
+    }
+
+    #[test]
+    fn test_from_samples() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let words: [&[u8]; 4] = [b"alpha ", b"beta ", b"gamma ", b"delta "];
+        let image = (0..1000)
+            .flat_map(|_| words[rng.gen_range(0..words.len())].to_vec())
+            .collect::<Vec<_>>();
+        let blob = b"key=value;key=value;".as_slice();
+        let data = [image.as_slice(), blob];
+        let config = BpeConfig::new().max_merges(20);
+
+        // everything fits: the same as training on the whole inputs
+        let whole = Bpe::from_samples(&data, &config, &Sampling::new(), |_| {});
+        let bpe = Bpe::with_config(&data, &config);
+        assert_eq!(whole.ids_to_tokens(), bpe.ids_to_tokens());
+        assert_eq!(whole.report(), bpe.report());
+
+        let sampling = Sampling::new()
+            .window_len(64)
+            .memory_budget(1000 * BYTES_PER_SYMBOL);
+        let mut events = vec![];
+        let sampled = Bpe::from_samples(&data, &config, &sampling, |event| events.push(event));
+
+        // the blob and 15 windows of the image; adjacent windows are counted as one
+        assert!(matches!(
+            events[0],
+            TrainProgress::Sampled {
+                windows: 2..=16,
+                symbols: 980
+            }
+        ));
+        assert_eq!(events[20], TrainProgress::Merged { merges: 20 });
+        assert_eq!(
+            events[21..],
+            [1, 2].map(|inputs| TrainProgress::Encoded { inputs })
+        );
+        assert_eq!(sampled.ids_to_tokens().len(), 276);
+
+        // a sixth of the image is enough to learn its words
+        assert_eq!(sampled.decode(&sampled.encode(blob)), blob);
+        assert_eq!(sampled.encode(b"alpha gamma ").len(), 2);
+        assert_eq!(sampled.decode(&sampled.encode(&image)), image);
+
+        let report = sampled.report();
+        assert_eq!(report.inputs[0].bytes, image.len());
+        // counted in chunks of 1000 symbols: words cut at chunk edges take a few more tokens
+        let ranked = sampled.encode_ranked(&image).len();
+        let chunks = image.len().div_ceil(1000);
+        assert!((ranked..ranked + 3 * chunks).contains(&report.inputs[0].tokens));
+
+        let again = Bpe::from_samples(&data, &config, &sampling, |_| {});
+        assert_eq!(again.ids_to_tokens(), sampled.ids_to_tokens());
+    }
+
+    #[test]
+    fn test_encode_ranked() {
+        // greedy encoding merges (1 2) before the higher ranked (2 3)
+        let bpe = Bpe::new(&[&[2, 3, 2, 3, 2, 3, 1, 2, 1, 2]]);
+        assert_eq!(
+            bpe.ids_to_tokens().get(&TokenId(256)),
+            Some(&Token::Merge(TokenId(2), TokenId(3)))
+        );
+        assert_eq!(
+            bpe.ids_to_tokens().get(&TokenId(257)),
+            Some(&Token::Merge(TokenId(1), TokenId(2)))
+        );
+        assert_eq!(
+            bpe.encode_ranked(&[1, 2, 3]),
+            vec![TokenId(1), TokenId(256)]
+        );
+        assert_eq!(bpe.encode(&[1, 2, 3]), vec![TokenId(257), TokenId(3)]);
+        assert_eq!(bpe.decode(&bpe.encode_ranked(&[1, 2, 3])), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_encode_ranked_matches_training() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        let structured = b"header:0001;data:abcabcabd;header:0002;data:abcabdabd;".repeat(4);
+        let random = (0..3)
+            .map(|_| {
+                let len = rng.gen_range(0..500);
+                (0..len).map(|_| rng.gen_range(0..4)).collect::<Vec<u8>>()
+            })
+            .collect::<Vec<_>>();
+
+        // runs of lengths without a run token of their own
+        let mut runs = b"head".to_vec();
+        for len in [10, 10, 11, 12, 13] {
+            runs.extend(vec![0; len]);
+            runs.extend(b"xy");
+        }
+
+        let mut data: Vec<&[u8]> = random.iter().map(|x| x.as_slice()).collect();
+        data.push(&structured);
+        data.push(&runs);
+
+        for config in [
+            BpeConfig::new(),
+            BpeConfig::new().max_token_len(5),
+            BpeConfig::new().max_merges(20),
+            BpeConfig::new().boundaries(Boundaries::EveryN(7)),
+            BpeConfig::new().min_run_len(4),
+        ] {
+            let (bpe, patterns) = Bpe::train(&data, &config);
+
+            for (input, pattern) in data.iter().zip(patterns) {
+                assert_eq!(bpe.encode_ranked(input), pattern);
+            }
+            // merges are only learned between the pieces the encoders produce
+            assert!(bpe.ids_to_tokens().values().all(|token| match *token {
+                Token::Merge(id0, id1) => [id0, id1]
+                    .iter()
+                    .all(|id| !matches!(bpe.ids_to_tokens()[id], Token::Run(..))),
+                _ => true,
+            }));
+        }
+    }
+
+    #[test]
+    fn test_boundaries() {
+        let data: &[&[u8]] = &[b"ab\nab\nab\n", b"b\nab\na"];
+
+        let spans_lines = |bpe: &Bpe| {
+            bpe.ids_to_tokens().keys().any(|&id| {
+                let bytes = bpe.decode(&[id]);
+                bytes[..bytes.len() - 1].contains(&b'\n')
+            })
+        };
+
+        assert!(spans_lines(&Bpe::new(data)));
+
+        let bpe = Bpe::with_config(data, &BpeConfig::new().boundaries(Boundaries::Newlines));
+        assert!(!spans_lines(&bpe));
+
+        let ids = bpe.encode(b"ab\nab\n");
+        assert_eq!(ids.len(), 2);
+        assert_eq!(bpe.decode(&ids), b"ab\nab\n");
+        assert_eq!(bpe.encode_ranked(b"ab\nab\n"), ids);
+        assert_eq!(bpe.encode(b"ab\n"), ids[..1]);
+    }
+
+    #[test]
+    fn test_encode_all() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let data = (0..4)
+            .map(|_| (0..500).map(|_| rng.gen_range(0..4)).collect::<Vec<u8>>())
+            .collect::<Vec<_>>();
+        let data = data.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        let config = BpeConfig::new().boundaries(Boundaries::EveryN(50));
+        let (bpe, patterns) = Bpe::train(&data, &config);
+
+        for mode in [EncodeMode::Greedy, EncodeMode::Ranked] {
+            let expected = data
+                .iter()
+                .map(|x| bpe.encode_with(x, mode))
+                .collect::<Vec<_>>();
+            assert_eq!(bpe.encode_all(&data, mode), expected);
+        }
+        assert_eq!(bpe.encode_all(&data, EncodeMode::Ranked), patterns);
+    }
+
+    #[test]
+    fn test_token_queries() {
+        let bpe = Bpe::new(&[&[1, 2, 3, 1, 2, 3], &[1, 2, 3, 4]]);
+        // 256: (2, 3), 257: (1, 256)
+
+        assert_eq!(bpe.token_bytes(TokenId(257)), Some(&[1, 2, 3][..]));
+        assert_eq!(bpe.token_len(TokenId(257)), Some(3));
+        assert_eq!(bpe.merge_depth(TokenId(257)), Some(2));
+        assert_eq!(bpe.merge_depth(TokenId(1)), Some(0));
+        assert_eq!(bpe.token_counts(TokenId(257)), Some(vec![2, 1]));
+        assert_eq!(bpe.token_counts(TokenId(4)), Some(vec![0, 1]));
+        assert_eq!(bpe.token_counts(TokenId(256)), Some(vec![0, 0]));
+
+        assert_eq!(bpe.token_bytes(TokenId(258)), None);
+        assert_eq!(bpe.token_len(TokenId(258)), None);
+        assert_eq!(bpe.merge_depth(TokenId(258)), None);
+        assert_eq!(bpe.token_counts(TokenId(258)), None);
+
+        let report = bpe.report();
+        assert_eq!(report.vocab_size, 258);
+        assert_eq!(report.merges, 2);
+        assert_eq!(report.max_merge_depth, 2);
+        assert_eq!(report.longest_tokens.len(), LONGEST_TOKENS);
+        assert_eq!(report.longest_tokens[0], (TokenId(257), vec![1, 2, 3]));
+        assert_eq!(report.longest_tokens[1], (TokenId(256), vec![2, 3]));
+        assert_eq!(report.longest_tokens[2], (TokenId(0), vec![0]));
+        assert_eq!(
+            report.inputs,
+            vec![
+                InputReport {
+                    bytes: 6,
+                    tokens: 2
+                },
+                InputReport {
+                    bytes: 4,
+                    tokens: 2
+                },
+            ]
+        );
+        assert_eq!(report.inputs[0].compression_ratio(), 3.0);
+
+        let mut saved = vec![];
+        bpe.write_to(&mut saved, VocabFormat::Binary).unwrap();
+        let loaded = Bpe::read_from(saved.as_slice()).unwrap();
+        assert_eq!(loaded.token_counts(TokenId(257)), Some(vec![]));
+        assert_eq!(loaded.report().inputs, vec![]);
+    }
+
+    #[test]
+    fn test_from_corpus() {
+        let dir = std::env::temp_dir().join(format!("arb_comp05_bpe_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0"), b"abcabc").unwrap();
+        std::fs::write(dir.join("1"), b"abcd").unwrap();
+
+        let bpe = Bpe::from_corpus(&[&dir], &BpeConfig::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+        let bpe = bpe.unwrap();
+
+        let trained = Bpe::new(&[b"abcabc", b"abcd"]);
+        assert_eq!(bpe.ids_to_tokens(), trained.ids_to_tokens());
+
+        // bytes not seen in the corpus fall back to byte tokens
+        let ids = bpe.encode(b"xabcx");
+        assert_eq!(
+            ids,
+            vec![TokenId(b'x' as usize), TokenId(257), TokenId(b'x' as usize)]
+        );
+        assert_eq!(bpe.decode(&ids), b"xabcx");
+    }
+
+    #[test]
+    fn test_extend() {
+        let data: &[&[u8]] = &[b"abcabcabc", b"abcd"];
+        let mut bpe = Bpe::new(data);
+        let original = bpe.ids_to_tokens().clone();
+        let ids = bpe.encode(b"abcabc");
+
+        // nothing left to learn from the same data
+        bpe.extend(data);
+        assert_eq!(bpe.ids_to_tokens(), &original);
+
+        bpe.extend(&[b"xyzxyz", b"abcxyz"]);
+        assert!(bpe.ids_to_tokens().len() > original.len());
+        assert!(original
+            .iter()
+            .all(|(id, token)| bpe.ids_to_tokens()[id] == *token));
+        assert_eq!(bpe.encode(b"abcabc"), ids);
+
+        let xyz = bpe.encode(b"xyz");
+        assert_eq!(xyz.len(), 1);
+        assert!(xyz[0].0 >= original.len());
+        let abc = bpe.encode(b"abc");
+        assert_eq!(abc.len(), 1);
+        assert_eq!(bpe.encode(b"abcxyz"), vec![abc[0], xyz[0]]);
+
+        assert_eq!(bpe.report().inputs.len(), 6);
+        assert_eq!(bpe.token_counts(xyz[0]), Some(vec![0, 0, 0, 0, 2, 1]));
+
+        let mut limited = Bpe::new(data);
+        limited.extend_with_config(&[b"xyzxyz"], &BpeConfig::new().max_merges(1));
+        assert_eq!(limited.ids_to_tokens().len(), original.len() + 1);
+    }
+
+    #[test]
+    fn test_prune() {
+        // ab is a stepping stone to cab, which can be split into (ca, b) once ca is learned
+        let mut bpe = Bpe::new(&[b"cab cab cab"]);
+        bpe.extend(&[b"xcaycaz"]);
+        let before = bpe.ids_to_tokens().len();
+        let ab = bpe.encode(b"ab");
+        assert_eq!(ab.len(), 1);
+        let old = bpe.encode(b"cab ab xcay");
+
+        let remap = bpe.prune();
+        assert_eq!(bpe.ids_to_tokens().len(), before - 1);
+        assert_eq!(bpe.encode(b"ab").len(), 2);
+        assert_eq!(bpe.encode(b"cab").len(), 1);
+        assert_eq!(remap.get(ab[0]).unwrap().len(), 2);
+        assert_eq!(bpe.decode(&remap.translate(&old).unwrap()), b"cab ab xcay");
+
+        // still a valid vocabulary, keeping the token counts
+        let mut written = vec![];
+        bpe.write_to(&mut written, VocabFormat::Binary).unwrap();
+        assert_eq!(
+            Bpe::read_from(written.as_slice()).unwrap().ids_to_tokens(),
+            bpe.ids_to_tokens()
+        );
+        let ca = bpe.encode(b"ca");
+        assert_eq!(bpe.token_counts(ca[0]), Some(vec![0, 2]));
+        assert_eq!(bpe.report().inputs[1].tokens, 5);
+
+        // pruning again changes nothing, nor does pruning a loaded vocabulary
+        let tokens = bpe.ids_to_tokens().clone();
+        assert_eq!(bpe.prune(), TokenRemap::identity(tokens.len()));
+        assert_eq!(bpe.ids_to_tokens(), &tokens);
+        let mut loaded = Bpe::read_from(written.as_slice()).unwrap();
+        loaded.prune();
+        assert_eq!(loaded.ids_to_tokens(), &tokens);
+    }
+
+    #[test]
+    fn test_prune_trained() {
+        // some tokens were only learned as parts of longer ones, and can be split otherwise
+        let data = b"cab cat abc bat abc ca tab ca bca bca bca ca tab cab ca bat ";
+        let mut bpe = Bpe::new(&[data]);
+        let before = bpe.tokens().len();
+        let old = bpe.encode(data);
+
+        let remap = bpe.prune();
+        assert!(bpe.tokens().len() < before);
+        assert_eq!(bpe.decode(&remap.translate(&old).unwrap()), data);
+        assert_eq!(bpe.decode(&bpe.encode(data)), data);
+    }
+
+    #[test]
+    fn test_symbols() {
+        // aligned 16-bit words: 0x3412 never appears, though its bytes do across words
+        let words: &[u16] = &[0x1234, 0x5678, 0x1234, 0x5678, 0x9abc];
+        let bpe = Bpe::from_symbols(&[words], &BpeConfig::new());
+        assert_eq!(bpe.ids_to_tokens().len(), 4);
+        assert_eq!(bpe.encode(words), vec![TokenId(3), TokenId(3), TokenId(2)]);
+        assert_eq!(bpe.decode(&bpe.encode(words)), words);
+        assert_eq!(bpe.token_symbols(TokenId(3)), Some(&[0x1234, 0x5678][..]));
+        assert!(matches!(
+            bpe.try_encode(&[0x3412]),
+            Err(Error::MissingSymbol(0x3412))
+        ));
+
+        // interned lines, diffed with the same matcher as bytes
+        let old = "start\nload a\nload b\nrun\nload a\nload b\nstop";
+        let new = "start\nload a\nload b\nrun\nload c\nstop";
+        let (ids, lines) = crate::symbol::intern(old.lines().chain(new.lines()));
+        let (old_ids, new_ids) = ids.split_at(old.lines().count());
+
+        let mut bpe = Bpe::from_symbols(&[old_ids], &BpeConfig::new());
+        assert_eq!(bpe.ids_to_tokens().len(), 6);
+        bpe.extend(&[new_ids]);
+        assert_eq!(bpe.encode(&[lines.len() as u32 - 1]).len(), 1);
+
+        let matches = greedy00(&bpe.encode(old_ids), &bpe.encode(new_ids));
+        let diff = matches
+            .iter()
+            .filter_map(|m| match m {
+                Matched::Diff(a, b) => Some((bpe.decode(a), bpe.decode(b))),
+                Matched::Same(_) => None,
+            })
+            .map(|(a, b)| (a.len(), b.len()))
+            .collect::<Vec<_>>();
+        assert!(!diff.is_empty());
+        assert!(diff.iter().all(|&(a, b)| a <= 3 && b <= 2));
+    }
+
+    #[test]
+    fn test_runs() {
+        let mut a = vec![0u8; 1000];
+        a[..4].copy_from_slice(b"head");
+        let mut b = a.clone();
+        b[500] = 0xff;
+
+        // without run tokens, padding builds a deep chain of Merge(x, x)
+        let plain = Bpe::new(&[&a, &b]);
+        assert!(plain.report().max_merge_depth >= 8);
+
+        // like pairs, runs only get a token if they occur often enough
+        let config = BpeConfig::new().min_run_len(16);
+        let once = Bpe::with_config(&[&a, &b], &config);
+        let runs = once.tokens().iter();
+        assert_eq!(
+            runs.filter(|token| matches!(token, Token::Run(..))).count(),
+            0
+        );
+
+        let bpe = Bpe::with_config(&[&a, &a, &b, &b], &config);
+        assert_eq!(bpe.min_run_len(), Some(16));
+        assert!(bpe.report().max_merge_depth <= 3);
+
+        let encoded = bpe.encode(&a);
+        assert_eq!(encoded.len(), 2);
+        assert_eq!(
+            bpe.ids_to_tokens()[&encoded[1]],
+            Token::Run(TokenId(0), 996)
+        );
+        assert_eq!(bpe.decode(&encoded), a);
+        assert_eq!(bpe.encode_ranked(&b).len(), 4);
+        assert_eq!(bpe.decode(&bpe.encode(&b)), b);
+
+        // a run length not seen in training is built from shorter run tokens
+        let longer = vec![0u8; 2000];
+        let encoded = bpe.encode(&longer);
+        assert_eq!(encoded[..2], [TokenId(258); 2]);
+        assert_eq!(encoded[2..], [TokenId(0); 8]);
+        assert_eq!(bpe.decode(&encoded), longer);
+        assert_eq!(bpe.encode(&[0; 15]), [TokenId(0); 15]);
+
+        assert!(bpe.to_dot(None).unwrap().contains("[label=\"x996\"]"));
+        assert_eq!(bpe.merge_depth(TokenId(256)), Some(1));
+
+        let mut pruned = Bpe::with_config(&[&a, &a, &b, &b], &config);
+        pruned.prune();
+        let runs = pruned.ids_to_tokens().values();
+        assert_eq!(
+            runs.filter(|token| matches!(token, Token::Run(..))).count(),
+            3
+        );
+        assert_eq!(pruned.decode(&pruned.encode(&b)), b);
+    }
 }
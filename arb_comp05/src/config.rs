@@ -0,0 +1,129 @@
+//! Training limits for [`crate::bpe::Bpe::with_config`]
+
+use crate::pretokenize::Boundaries;
+
+/// How much each training input's pair occurrences count when choosing merges
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Weighting {
+    /// Every occurrence counts once, so large inputs dominate.
+    #[default]
+    Occurrences,
+    /// Occurrences count in inverse proportion to the length of their input,
+    /// so every input contributes as much as the longest one.
+    PerInput,
+    /// Occurrences in input `i` count `weights[i]` times (once past the end of `weights`).
+    Custom(Vec<u64>),
+}
+
+impl Weighting {
+    /// The weight of each input, given their lengths.
+    pub(crate) fn input_weights(&self, lens: &[usize]) -> Vec<u64> {
+        match self {
+            Weighting::Occurrences => vec![1; lens.len()],
+            Weighting::PerInput => {
+                let longest = lens.iter().copied().max().unwrap_or(0);
+                let weight = |len: usize| (longest as f64 / len.max(1) as f64).round() as u64;
+                lens.iter().map(|&len| weight(len).max(1)).collect()
+            }
+            Weighting::Custom(weights) => (0..lens.len())
+                .map(|i| weights.get(i).copied().unwrap_or(1))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BpeConfig {
+    /// Stop once the vocabulary (including the 256 byte tokens) has this many tokens.
+    pub max_vocab_size: Option<usize>,
+    /// Only merge pairs that occur at least this many times.
+    pub min_pair_frequency: usize,
+    /// Only merge pairs that occur in at least this many distinct inputs, and only give runs
+    /// run tokens if they do. With 2, every merged token spells content shared between inputs.
+    pub min_inputs: usize,
+    /// How pair occurrences in the different inputs are weighted.
+    /// `min_pair_frequency` still counts plain occurrences.
+    pub weighting: Weighting,
+    /// Never create tokens that expand to more than this many bytes.
+    pub max_token_len: Option<usize>,
+    /// Stop after this many merges.
+    pub max_merges: Option<usize>,
+    /// Where merges may not cross, in training and in `encode`.
+    pub boundaries: Boundaries,
+    /// Encode each run of at least this many copies of a symbol (minimum 2) as one run token,
+    /// in training and in `encode`, instead of merging it pairwise. Like pairs, a run length
+    /// only gets a token if it occurs `min_pair_frequency` times; `encode` builds other
+    /// lengths from shorter run tokens.
+    pub min_run_len: Option<usize>,
+}
+
+impl Default for BpeConfig {
+    /// No limits: merge until no pair occurs more than once (the behavior of `Bpe::new`).
+    fn default() -> Self {
+        Self {
+            max_vocab_size: None,
+            min_pair_frequency: 2,
+            min_inputs: 1,
+            weighting: Weighting::Occurrences,
+            max_token_len: None,
+            max_merges: None,
+            boundaries: Boundaries::None,
+            min_run_len: None,
+        }
+    }
+}
+
+impl BpeConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_vocab_size(mut self, size: usize) -> Self {
+        self.max_vocab_size = Some(size);
+        self
+    }
+
+    pub fn min_pair_frequency(mut self, count: usize) -> Self {
+        self.min_pair_frequency = count;
+        self
+    }
+
+    pub fn min_inputs(mut self, count: usize) -> Self {
+        self.min_inputs = count;
+        self
+    }
+
+    pub fn weighting(mut self, weighting: Weighting) -> Self {
+        self.weighting = weighting;
+        self
+    }
+
+    pub fn max_token_len(mut self, len: usize) -> Self {
+        self.max_token_len = Some(len);
+        self
+    }
+
+    pub fn max_merges(mut self, count: usize) -> Self {
+        self.max_merges = Some(count);
+        self
+    }
+
+    pub fn boundaries(mut self, boundaries: Boundaries) -> Self {
+        self.boundaries = boundaries;
+        self
+    }
+
+    pub fn min_run_len(mut self, len: usize) -> Self {
+        self.min_run_len = Some(len.max(2));
+        self
+    }
+
+    pub(crate) fn allows_more_tokens(&self, vocab_size: usize, merges: usize) -> bool {
+        vocab_size < self.max_vocab_size.unwrap_or(usize::MAX)
+            && merges < self.max_merges.unwrap_or(usize::MAX)
+    }
+
+    pub(crate) fn allows_token_len(&self, len: usize) -> bool {
+        len <= self.max_token_len.unwrap_or(usize::MAX)
+    }
+}
@@ -0,0 +1,282 @@
+//! A self-describing compressed file: a vocabulary and the `TokenId` stream it encodes
+//!
+//! Layout: [`MAGIC`], a version byte, a [`Coding`] byte, the varint length of the vocabulary
+//! followed by the vocabulary in the binary [`crate::vocab_file`] format,
+//! the varint length of the original data, then the token stream:
+//!
+//! * [`Coding::Varint`]: the varint token count, then each `TokenId` as a varint
+//! * [`Coding::Huffman`]: a stream written by [`crate::entropy::encode`]
+//!
+//! Version 1 files have no coding byte and are always [`Coding::Varint`].
+
+use crate::bpe::Bpe;
+use crate::config::BpeConfig;
+use crate::entropy;
+use crate::error::{Error, Result};
+use crate::token::TokenId;
+use crate::varint::{read_varint, write_varint};
+use crate::vocab_file::VocabFormat;
+
+pub const MAGIC: &[u8; 8] = b"ACBPEPAK";
+pub const VERSION: u8 = 2;
+const SUPPORTED_VERSIONS: std::ops::RangeInclusive<u8> = 1..=VERSION;
+
+/// How the token stream of a container is stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Coding {
+    /// Each `TokenId` as a varint
+    #[default]
+    Varint,
+    /// A canonical Huffman code over the stream's `TokenId` frequencies
+    Huffman,
+}
+
+const CODING_VARINT: u8 = 0;
+const CODING_HUFFMAN: u8 = 1;
+
+fn invalid_data(msg: impl Into<String>) -> Error {
+    Error::InvalidContainer(msg.into())
+}
+
+/// Trains a vocabulary on `data` and stores `data` encoded with it.
+pub fn compress(data: &[u8], config: &BpeConfig) -> Vec<u8> {
+    compress_coded(data, config, Coding::Varint)
+}
+
+/// Like [`compress`], with a choice of token stream coding.
+pub fn compress_coded(data: &[u8], config: &BpeConfig, coding: Coding) -> Vec<u8> {
+    let (bpe, mut patterns) = Bpe::train(&[data], config);
+    write(&bpe, data.len(), &patterns.pop().unwrap(), coding)
+}
+
+/// Stores `data` encoded with an existing vocabulary.
+pub fn compress_with(bpe: &Bpe, data: &[u8]) -> Vec<u8> {
+    compress_with_coded(bpe, data, Coding::Varint)
+}
+
+/// Like [`compress_with`], with a choice of token stream coding.
+pub fn compress_with_coded(bpe: &Bpe, data: &[u8], coding: Coding) -> Vec<u8> {
+    write(bpe, data.len(), &bpe.encode_ranked(data), coding)
+}
+
+/// Restores the data stored by [`compress`] or [`compress_with`].
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let (bpe, ids, len) = read_parts(data)?;
+
+    // checked before decoding, so tokens expanding to far more than that are never expanded
+    let mut decoded_len = 0usize;
+    for &id in &ids {
+        let token_len = bpe.token_len(id).ok_or(Error::UnknownTokenId(id))?;
+        decoded_len = match decoded_len.checked_add(token_len) {
+            Some(decoded_len) if decoded_len <= len => decoded_len,
+            _ => return Err(invalid_data(format!("decodes to over {len} bytes"))),
+        };
+    }
+    if decoded_len != len {
+        return Err(invalid_data(format!(
+            "decodes to {decoded_len} bytes, expected {len}"
+        )));
+    }
+    bpe.try_decode(&ids)
+}
+
+/// Reads the vocabulary and token stream of a container without decoding it.
+pub fn read(data: &[u8]) -> Result<(Bpe, Vec<TokenId>)> {
+    read_parts(data).map(|(bpe, ids, _)| (bpe, ids))
+}
+
+fn write(bpe: &Bpe, len: usize, ids: &[TokenId], coding: Coding) -> Vec<u8> {
+    let mut vocab = vec![];
+    bpe.write_to(&mut vocab, VocabFormat::Binary)
+        .expect("writing to a Vec cannot fail");
+
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.push(match coding {
+        Coding::Varint => CODING_VARINT,
+        Coding::Huffman => CODING_HUFFMAN,
+    });
+    write_varint(&mut out, vocab.len() as u64);
+    out.extend(vocab);
+    write_varint(&mut out, len as u64);
+
+    match coding {
+        Coding::Varint => {
+            write_varint(&mut out, ids.len() as u64);
+            ids.iter()
+                .for_each(|id| write_varint(&mut out, id.0 as u64));
+        }
+        Coding::Huffman => out.extend(entropy::encode(ids)),
+    }
+    out
+}
+
+fn read_parts(data: &[u8]) -> Result<(Bpe, Vec<TokenId>, usize)> {
+    let truncated = || invalid_data("truncated container");
+    let read_usize = |data: &mut &[u8]| {
+        read_varint(data)
+            .and_then(|x| usize::try_from(x).ok())
+            .ok_or_else(truncated)
+    };
+
+    let mut data = data
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| invalid_data("not a compressed file"))?;
+
+    let (&version, rest) = data.split_first().ok_or_else(truncated)?;
+    data = rest;
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(invalid_data(format!(
+            "unsupported container version {version}"
+        )));
+    }
+
+    let coding = if version >= 2 {
+        let (&coding, rest) = data.split_first().ok_or_else(truncated)?;
+        data = rest;
+        match coding {
+            CODING_VARINT => Coding::Varint,
+            CODING_HUFFMAN => Coding::Huffman,
+            _ => return Err(invalid_data(format!("unknown stream coding {coding}"))),
+        }
+    } else {
+        Coding::Varint
+    };
+
+    let vocab_len = read_usize(&mut data)?;
+    if data.len() < vocab_len {
+        return Err(truncated());
+    }
+    let (vocab, rest) = data.split_at(vocab_len);
+    data = rest;
+    let bpe = Bpe::read_from(vocab)?;
+
+    let len = read_usize(&mut data)?;
+    let ids = match coding {
+        Coding::Varint => {
+            let count = read_usize(&mut data)?;
+            (0..count)
+                .map(|_| read_usize(&mut data).map(TokenId))
+                .collect::<Result<Vec<_>>>()?
+        }
+        Coding::Huffman => {
+            let (ids, rest) = entropy::decode_prefix(data)?;
+            data = rest;
+            ids
+        }
+    };
+
+    if !data.is_empty() {
+        return Err(invalid_data("trailing data after token stream"));
+    }
+    Ok((bpe, ids, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretokenize::Boundaries;
+    use crate::token::Token;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(12);
+        let random = (0..5000).map(|_| rng.gen()).collect::<Vec<u8>>();
+        let structured = (0..200u32)
+            .flat_map(|i| [b"record".as_slice(), &i.to_le_bytes(), &[0; 10]].concat())
+            .collect::<Vec<u8>>();
+
+        for data in [vec![], vec![7], random, structured.clone()] {
+            for config in [BpeConfig::new(), BpeConfig::new().max_merges(10)] {
+                for coding in [Coding::Varint, Coding::Huffman] {
+                    let compressed = compress_coded(&data, &config, coding);
+                    assert_eq!(decompress(&compressed).unwrap(), data);
+                }
+            }
+        }
+        assert!(
+            compress_coded(&structured, &BpeConfig::new(), Coding::Huffman).len()
+                < compress(&structured, &BpeConfig::new()).len()
+        );
+
+        // a vocabulary is a net win on repetitive data
+        assert!(compress(&structured, &BpeConfig::new()).len() < structured.len() / 2);
+
+        let bpe = Bpe::new(&[&structured]);
+        let data = b"record\x01\x02\x03\x04 and more".to_vec();
+        let compressed = compress_with(&bpe, &data);
+        assert_eq!(decompress(&compressed).unwrap(), data);
+
+        let (read_bpe, ids) = read(&compressed).unwrap();
+        assert_eq!(read_bpe.ids_to_tokens(), bpe.ids_to_tokens());
+        assert_eq!(ids, bpe.encode_ranked(&data));
+    }
+
+    #[test]
+    fn test_errors() {
+        let compressed = compress(b"abcabcabc", &BpeConfig::new());
+
+        assert!(decompress(b"not compressed").is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+        assert!(decompress(&[compressed.as_slice(), &[0]].concat()).is_err());
+
+        let mut wrong_version = compressed.clone();
+        wrong_version[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            decompress(&wrong_version),
+            Err(Error::InvalidContainer(_))
+        ));
+
+        let (bpe, ids) = read(&compressed).unwrap();
+        let unknown_id = write(
+            &bpe,
+            9,
+            &[TokenId(bpe.ids_to_tokens().len())],
+            Coding::Huffman,
+        );
+        assert!(matches!(
+            decompress(&unknown_id),
+            Err(Error::UnknownTokenId(_))
+        ));
+
+        let wrong_len = write(&bpe, 10, &ids, Coding::Varint);
+        assert!(matches!(
+            decompress(&wrong_len),
+            Err(Error::InvalidContainer(_))
+        ));
+
+        // a token doubling its length 60 times is rejected without being expanded
+        let mut tokens = (0..=u8::MAX).map(Token::Symbol).collect::<Vec<_>>();
+        for i in 0..60 {
+            let id = TokenId(if i == 0 { b'a'.into() } else { 255 + i });
+            tokens.push(Token::Merge(id, id));
+        }
+        let top = TokenId(tokens.len() - 1);
+        let bpe = Bpe::from_tokens(tokens, Boundaries::None, None);
+        let doubling = write(&bpe, 9, &[top], Coding::Varint);
+        assert!(matches!(
+            decompress(&doubling),
+            Err(Error::InvalidContainer(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_version_1() {
+        let bpe = Bpe::new(&[b"abab"]);
+        let ids = bpe.encode_ranked(b"abab");
+
+        let mut vocab = vec![];
+        bpe.write_to(&mut vocab, VocabFormat::Binary).unwrap();
+        let mut compressed = MAGIC.to_vec();
+        compressed.push(1);
+        write_varint(&mut compressed, vocab.len() as u64);
+        compressed.extend(vocab);
+        write_varint(&mut compressed, 4);
+        write_varint(&mut compressed, ids.len() as u64);
+        ids.iter()
+            .for_each(|id| write_varint(&mut compressed, id.0 as u64));
+
+        assert_eq!(decompress(&compressed).unwrap(), b"abab");
+    }
+}
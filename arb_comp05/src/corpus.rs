@@ -0,0 +1,64 @@
+//! Training corpora read from disk
+
+use crate::error::{Error, Result};
+use std::path::{Path, PathBuf};
+
+/// Reads every file under `paths`, each of which is a file or a directory.
+///
+/// Directories are walked recursively and their files are read in path order,
+/// so the same corpus always gives the same training inputs.
+pub fn read_corpus(paths: &[impl AsRef<Path>]) -> Result<Vec<Vec<u8>>> {
+    let mut files = vec![];
+    for path in paths {
+        collect_files(path.as_ref(), &mut files)?;
+    }
+
+    files
+        .into_iter()
+        .map(|path| std::fs::read(&path).map_err(Error::file(path)))
+        .collect()
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = std::fs::read_dir(path)
+        .and_then(|dir| {
+            dir.map(|entry| entry.map(|x| x.path()))
+                .collect::<std::io::Result<Vec<_>>>()
+        })
+        .map_err(Error::file(path))?;
+    entries.sort();
+
+    for entry in entries {
+        collect_files(&entry, files)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_corpus() {
+        let dir = std::env::temp_dir().join(format!("arb_comp05_corpus_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        std::fs::write(dir.join("b/1"), b"b1").unwrap();
+        std::fs::write(dir.join("a"), b"a").unwrap();
+        std::fs::write(dir.join("c"), b"c").unwrap();
+
+        let corpus = read_corpus(&[&dir, &dir.join("a")]);
+        let missing = read_corpus(&[dir.join("missing")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            corpus.unwrap(),
+            vec![b"a".to_vec(), b"b1".to_vec(), b"c".to_vec(), b"a".to_vec()]
+        );
+        assert!(matches!(missing, Err(Error::File { .. })));
+    }
+}
@@ -24,6 +24,7 @@ pub fn get_diffs(
             continue;
         }
 
+        let offset = i;
         i += 1;
 
         match (a_next, b_next) {
@@ -32,19 +33,23 @@ pub fn get_diffs(
                     a_diff.push(HexCell::Same {
                         value: *a,
                         source_id: 0,
+                        offset,
                     });
                     b_diff.push(HexCell::Same {
                         value: *b,
                         source_id: 0,
+                        offset,
                     });
                 } else {
                     a_diff.push(HexCell::Diff {
                         value: *a,
                         source_id: 0,
+                        offset,
                     });
                     b_diff.push(HexCell::Diff {
                         value: *b,
                         source_id: 0,
+                        offset,
                     });
                 }
             }
@@ -0,0 +1,123 @@
+//! Graphviz (DOT) export of the merge tree
+
+use crate::bpe::Bpe;
+use crate::error::{Error, Result};
+use crate::token::{Token, TokenId};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Node labels show at most this many bytes of a token's expansion.
+const SHOWN_BYTES: usize = 16;
+
+/// Renders the merge DAG below `roots` (every merge and run token if `None`) as a DOT digraph.
+///
+/// Each node is labeled with its id, its bytes (hex and escaped ASCII) and,
+/// for a trained vocabulary, how often it occurs in the training inputs.
+/// Edges run from a merge to its left (`0`) and right (`1`) parts,
+/// and from a run to its symbol (`x` and the run length).
+pub fn to_dot(bpe: &Bpe, roots: Option<&[TokenId]>) -> Result<String> {
+    let tokens = bpe.tokens();
+    let mut stack = match roots {
+        Some(roots) => roots.to_vec(),
+        None => (tokens.iter().enumerate())
+            .filter(|(_, token)| !matches!(token, Token::Symbol(_)))
+            .map(|(i, _)| TokenId(i))
+            .collect(),
+    };
+
+    let mut nodes = BTreeSet::new();
+    while let Some(id) = stack.pop() {
+        let token = tokens.get(id.0).ok_or(Error::UnknownTokenId(id))?;
+        if nodes.insert(id) {
+            match *token {
+                Token::Merge(id0, id1) => stack.extend([id0, id1]),
+                Token::Run(id, _) => stack.push(id),
+                Token::Symbol(_) => {}
+            }
+        }
+    }
+
+    let mut dot = String::from("digraph bpe {\n    node [shape=box, fontname=monospace];\n");
+    for &id in &nodes {
+        let bytes = bpe.token_bytes(id).unwrap();
+        let shown = &bytes[..bytes.len().min(SHOWN_BYTES)];
+        let more = if bytes.len() > SHOWN_BYTES {
+            " ..."
+        } else {
+            ""
+        };
+
+        let hex = shown.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>();
+        let mut label = format!(
+            "#{}\n{}{more}\n{}{more}",
+            id.0,
+            hex.join(" "),
+            shown.escape_ascii()
+        );
+        if let Some(counts) = bpe.token_counts(id).filter(|x| !x.is_empty()) {
+            write!(label, "\ncount {}", counts.iter().sum::<usize>()).unwrap();
+        }
+        writeln!(dot, "    {} [label=\"{}\"];", id.0, escape(&label)).unwrap();
+    }
+    for &id in &nodes {
+        match tokens[id.0] {
+            Token::Merge(id0, id1) => {
+                writeln!(dot, "    {} -> {} [label=\"0\"];", id.0, id0.0).unwrap();
+                writeln!(dot, "    {} -> {} [label=\"1\"];", id.0, id1.0).unwrap();
+            }
+            Token::Run(symbol, len) => {
+                writeln!(dot, "    {} -> {} [label=\"x{len}\"];", id.0, symbol.0).unwrap();
+            }
+            Token::Symbol(_) => {}
+        }
+    }
+    dot.push_str("}\n");
+
+    Ok(dot)
+}
+
+/// Escapes a label for a DOT string, keeping line breaks.
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_dot() {
+        let bpe = Bpe::new(&[b"a\"ba\"b"]);
+        // 256: (", b) 257: (a, 256)
+
+        let dot = to_dot(&bpe, Some(&[TokenId(257)])).unwrap();
+        assert_eq!(
+            dot,
+            [
+                "digraph bpe {",
+                "    node [shape=box, fontname=monospace];",
+                "    34 [label=\"#34\\n22\\n\\\\\\\"\\ncount 0\"];",
+                "    97 [label=\"#97\\n61\\na\\ncount 0\"];",
+                "    98 [label=\"#98\\n62\\nb\\ncount 0\"];",
+                "    256 [label=\"#256\\n22 62\\n\\\\\\\"b\\ncount 0\"];",
+                "    257 [label=\"#257\\n61 22 62\\na\\\\\\\"b\\ncount 2\"];",
+                "    256 -> 34 [label=\"0\"];",
+                "    256 -> 98 [label=\"1\"];",
+                "    257 -> 97 [label=\"0\"];",
+                "    257 -> 256 [label=\"1\"];",
+                "}",
+                "",
+            ]
+            .join("\n")
+        );
+
+        assert_eq!(to_dot(&bpe, None).unwrap(), dot);
+        assert!(matches!(
+            to_dot(&bpe, Some(&[TokenId(258)])),
+            Err(Error::UnknownTokenId(TokenId(258)))
+        ));
+    }
+}
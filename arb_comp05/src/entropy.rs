@@ -0,0 +1,358 @@
+//! Entropy coding of `TokenId` streams with a canonical Huffman code
+//!
+//! An encoded stream is self-describing: a code table, the varint token count,
+//! then the codes, most significant bit first, with the last byte zero-padded.
+//!
+//! The code table is whichever of these is smaller:
+//!
+//! * sparse: a `0` byte, the varint number of distinct ids,
+//!   then per id (ascending) the varint gap from the previous id and the varint code length
+//! * dense: a `1` byte, the varint highest id plus one,
+//!   then per id from 0 a code length byte (`0` for an unused id)
+
+use crate::error::{Error, Result};
+use crate::token::TokenId;
+use crate::varint::{read_varint, write_varint};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+const TABLE_SPARSE: u8 = 0;
+const TABLE_DENSE: u8 = 1;
+
+fn invalid_data(msg: impl Into<String>) -> Error {
+    Error::InvalidStream(msg.into())
+}
+
+/// Occurrences of each id in `ids`.
+pub fn id_counts(ids: &[TokenId]) -> BTreeMap<TokenId, usize> {
+    let mut counts = BTreeMap::new();
+    ids.iter()
+        .for_each(|&id| *counts.entry(id).or_default() += 1);
+    counts
+}
+
+/// The Shannon entropy of `ids` (as an order-0 source), in bits for the whole stream.
+///
+/// This is the lower bound for any coding that, like [`encode`], codes ids independently.
+pub fn entropy_bits(ids: &[TokenId]) -> f64 {
+    let total = ids.len() as f64;
+    id_counts(ids)
+        .values()
+        .map(|&count| {
+            let count = count as f64;
+            -count * (count / total).log2()
+        })
+        .sum()
+}
+
+/// Huffman code lengths for ids with the given counts.
+///
+/// A single id gets a 1-bit code. Lengths stay far below 64 bits for any stream
+/// that fits in memory (reaching 64 needs Fibonacci-sized counts).
+fn code_lengths(counts: &BTreeMap<TokenId, usize>) -> BTreeMap<TokenId, u32> {
+    if counts.len() == 1 {
+        return counts.keys().map(|&id| (id, 1)).collect();
+    }
+
+    // leaves are 0..n, internal nodes are appended; ties break on node index for determinism
+    let ids = counts.keys().copied().collect::<Vec<_>>();
+    let mut parents = vec![usize::MAX; ids.len()];
+    let mut heap = counts
+        .values()
+        .enumerate()
+        .map(|(i, &count)| Reverse((count, i)))
+        .collect::<BinaryHeap<_>>();
+
+    while heap.len() > 1 {
+        let Reverse((count0, node0)) = heap.pop().unwrap();
+        let Reverse((count1, node1)) = heap.pop().unwrap();
+        let parent = parents.len();
+        parents.push(usize::MAX);
+        parents[node0] = parent;
+        parents[node1] = parent;
+        heap.push(Reverse((count0 + count1, parent)));
+    }
+
+    // parents always come after their children, so depths can be filled from the root down
+    let mut depths = vec![0; parents.len()];
+    for node in (0..parents.len()).rev() {
+        if parents[node] != usize::MAX {
+            depths[node] = depths[parents[node]] + 1;
+        }
+    }
+
+    ids.into_iter()
+        .enumerate()
+        .map(|(i, id)| (id, depths[i]))
+        .collect()
+}
+
+/// Canonical codes: ids ordered by (length, id) get consecutive codes.
+fn canonical_order(lengths: &BTreeMap<TokenId, u32>) -> Vec<(TokenId, u32)> {
+    let mut order = lengths
+        .iter()
+        .map(|(&id, &len)| (id, len))
+        .collect::<Vec<_>>();
+    order.sort_by_key(|&(id, len)| (len, id));
+    order
+}
+
+/// Entropy-codes `ids` with a Huffman code built from their own frequencies.
+pub fn encode(ids: &[TokenId]) -> Vec<u8> {
+    let lengths = code_lengths(&id_counts(ids));
+
+    let mut out = write_table(&lengths);
+    write_varint(&mut out, ids.len() as u64);
+
+    let mut codes = HashMap::new();
+    let mut code = 0u64;
+    let mut previous_len = 0;
+    for (id, len) in canonical_order(&lengths) {
+        code <<= len - previous_len;
+        codes.insert(id, (code, len));
+        code += 1;
+        previous_len = len;
+    }
+
+    let mut byte = 0u8;
+    let mut bits = 0;
+    for id in ids {
+        let (code, len) = codes[id];
+        for i in (0..len).rev() {
+            byte = (byte << 1) | ((code >> i) & 1) as u8;
+            bits += 1;
+            if bits == 8 {
+                out.push(byte);
+                (byte, bits) = (0, 0);
+            }
+        }
+    }
+    if bits > 0 {
+        out.push(byte << (8 - bits));
+    }
+    out
+}
+
+fn write_table(lengths: &BTreeMap<TokenId, u32>) -> Vec<u8> {
+    let mut sparse = vec![TABLE_SPARSE];
+    write_varint(&mut sparse, lengths.len() as u64);
+    let mut previous = 0;
+    for (&id, &len) in lengths {
+        write_varint(&mut sparse, (id.0 - previous) as u64);
+        write_varint(&mut sparse, len as u64);
+        previous = id.0;
+    }
+
+    // the dense table has a byte per id, so it can only be smaller for low ids
+    let end = lengths.keys().next_back().map_or(0, |id| id.0 + 1);
+    if end >= sparse.len() {
+        return sparse;
+    }
+
+    let mut dense = vec![TABLE_DENSE];
+    write_varint(&mut dense, end as u64);
+    dense.extend((0..end).map(|id| lengths.get(&TokenId(id)).map_or(0, |&len| len as u8)));
+
+    if dense.len() < sparse.len() {
+        dense
+    } else {
+        sparse
+    }
+}
+
+fn read_table(data: &mut &[u8]) -> Result<BTreeMap<TokenId, u32>> {
+    let truncated = || invalid_data("truncated entropy-coded stream");
+    let read_usize = |data: &mut &[u8]| {
+        read_varint(data)
+            .and_then(|x| usize::try_from(x).ok())
+            .ok_or_else(truncated)
+    };
+    let check_len = |len: usize| {
+        if (1..=64).contains(&len) {
+            Ok(len as u32)
+        } else {
+            Err(invalid_data(format!("invalid code length {len}")))
+        }
+    };
+
+    let (&tag, rest) = data.split_first().ok_or_else(truncated)?;
+    *data = rest;
+
+    let mut lengths = BTreeMap::new();
+    match tag {
+        TABLE_SPARSE => {
+            let symbols = read_usize(data)?;
+            let mut id = 0usize;
+            for i in 0..symbols {
+                let gap = read_usize(data)?;
+                if i > 0 && gap == 0 {
+                    return Err(invalid_data("id listed twice in code table"));
+                }
+                id = id.checked_add(gap).ok_or_else(truncated)?;
+                lengths.insert(TokenId(id), check_len(read_usize(data)?)?);
+            }
+        }
+        TABLE_DENSE => {
+            let end = read_usize(data)?;
+            if data.len() < end {
+                return Err(truncated());
+            }
+            let (table, rest) = data.split_at(end);
+            *data = rest;
+            for (id, &len) in table.iter().enumerate() {
+                if len != 0 {
+                    lengths.insert(TokenId(id), check_len(len as usize)?);
+                }
+            }
+        }
+        _ => return Err(invalid_data(format!("unknown code table tag {tag}"))),
+    }
+    Ok(lengths)
+}
+
+/// Decodes a stream written by [`encode`].
+pub fn decode(data: &[u8]) -> Result<Vec<TokenId>> {
+    let (ids, rest) = decode_prefix(data)?;
+    if !rest.is_empty() {
+        return Err(invalid_data("trailing data after entropy-coded stream"));
+    }
+    Ok(ids)
+}
+
+/// Decodes a stream written by [`encode`] from the start of `data`, returning the unread rest.
+pub(crate) fn decode_prefix(mut data: &[u8]) -> Result<(Vec<TokenId>, &[u8])> {
+    let truncated = || invalid_data("truncated entropy-coded stream");
+    let read_usize = |data: &mut &[u8]| {
+        read_varint(data)
+            .and_then(|x| usize::try_from(x).ok())
+            .ok_or_else(truncated)
+    };
+
+    let lengths = read_table(&mut data)?;
+    let count = read_usize(&mut data)?;
+
+    // per length: the first canonical code and the index of its id in `order`
+    let order = canonical_order(&lengths);
+    let max_len = order.last().map_or(0, |&(_, len)| len);
+    let mut first_code = vec![0u64; max_len as usize + 1];
+    let mut first_index = vec![0usize; max_len as usize + 1];
+    let mut code = 0u64;
+    let mut index = 0;
+    for len in 1..=max_len {
+        first_code[len as usize] = code;
+        first_index[len as usize] = index;
+        let n = order[index..]
+            .iter()
+            .take_while(|&&(_, l)| l == len)
+            .count();
+        index += n;
+        code = (code + n as u64) << 1;
+    }
+
+    let mut ids = Vec::with_capacity(count.min(data.len() * 8));
+    let mut bit = 0;
+    for _ in 0..count {
+        let mut code = 0u64;
+        let mut len = 0;
+        loop {
+            let byte = data.get(bit / 8).ok_or_else(truncated)?;
+            code = (code << 1) | u64::from((byte >> (7 - bit % 8)) & 1);
+            bit += 1;
+            len += 1;
+            if len > max_len {
+                return Err(invalid_data("invalid code in entropy-coded stream"));
+            }
+
+            if let Some(offset) = code.checked_sub(first_code[len as usize]) {
+                let index = first_index[len as usize].saturating_add(offset as usize);
+                if order.get(index).is_some_and(|&(_, l)| l == len) {
+                    ids.push(order[index].0);
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok((ids, &data[bit.div_ceil(8)..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    fn ids(x: &[usize]) -> Vec<TokenId> {
+        x.iter().map(|&x| TokenId(x)).collect()
+    }
+
+    #[test]
+    fn test_code_lengths() {
+        let counts = id_counts(&ids(&[5, 5, 5, 5, 7, 7, 300, 1000]));
+        let lengths = code_lengths(&counts);
+        assert_eq!(
+            lengths.into_iter().collect::<Vec<_>>(),
+            vec![
+                (TokenId(5), 1),
+                (TokenId(7), 2),
+                (TokenId(300), 3),
+                (TokenId(1000), 3)
+            ]
+        );
+
+        assert_eq!(
+            code_lengths(&id_counts(&ids(&[9, 9])))
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![(TokenId(9), 1)]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(13);
+        let skewed = (0..10000)
+            .map(|_| TokenId(rng.gen_range(0..20usize).pow(3)))
+            .collect::<Vec<_>>();
+
+        for stream in [
+            vec![],
+            ids(&[3]),
+            ids(&[3, 3, 3]),
+            ids(&[0, 70000, 0]),
+            (0..3000).map(|i| TokenId(i % 300)).collect(),
+            skewed,
+        ] {
+            let encoded = encode(&stream);
+            assert_eq!(decode(&encoded).unwrap(), stream);
+
+            // Huffman needs at least the entropy, and less than one extra bit per token
+            let bits = 8.0 * encoded.len() as f64;
+            let bound = entropy_bits(&stream);
+            assert!(bits >= bound);
+            assert!(bits < bound + stream.len() as f64 + 1000.0);
+        }
+    }
+
+    #[test]
+    fn test_tables() {
+        let sparse = encode(&ids(&[0, 70000, 0]));
+        assert_eq!(sparse[0], TABLE_SPARSE);
+
+        let all = (0..300).map(TokenId).collect::<Vec<_>>();
+        let dense = encode(&all);
+        assert_eq!(dense[0], TABLE_DENSE);
+        assert!(dense.len() < 300 * 2 + 300 + 10);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let encoded = encode(&ids(&[1, 2, 2, 3, 3, 3]));
+        assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode(&[encoded.as_slice(), &[0]].concat()).is_err());
+        assert!(decode(&[TABLE_SPARSE, 1, 0, 65, 1]).is_err());
+        assert!(decode(&[TABLE_SPARSE, 2, 4, 1, 0, 1, 0]).is_err());
+        assert!(decode(&[TABLE_DENSE, 1, 65, 1, 0]).is_err());
+        assert!(decode(&[TABLE_DENSE, 2, 1]).is_err());
+        assert!(matches!(decode(&[2, 0, 0]), Err(Error::InvalidStream(_))));
+    }
+}
@@ -0,0 +1,97 @@
+//! Crate-wide error type
+
+use crate::token::TokenId;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A token id that is not in the vocabulary
+    UnknownTokenId(TokenId),
+    /// A symbol (by [`crate::symbol::Symbol::to_u64`]) with no token in the vocabulary
+    ///
+    /// Was `MissingByteToken(u8)` before vocabularies were generic over [`crate::symbol::Symbol`].
+    MissingSymbol(u64),
+    /// A token that should have been expanded to a symbol token, but was not
+    ///
+    /// Was `NotAByte`.
+    NotASymbol(TokenId),
+    /// A saved vocabulary that cannot be used as a `Bpe`
+    InvalidVocabulary(String),
+    /// A compressed file that cannot be read (see [`crate::container`])
+    InvalidContainer(String),
+    /// An entropy-coded token stream that cannot be decoded (see [`crate::entropy`])
+    InvalidStream(String),
+    /// An I/O error on a named file
+    File {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn file(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        move |source| Self::File {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Attaches a file name to a bare I/O error.
+    pub(crate) fn with_path(self, path: impl Into<PathBuf>) -> Self {
+        match self {
+            Self::Io(source) => Self::file(path)(source),
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownTokenId(id) => write!(f, "TokenId {} not in encoded set", id.0),
+            Self::MissingSymbol(x) => write!(f, "symbol {x:#04x} has no token"),
+            Self::NotASymbol(id) => {
+                write!(
+                    f,
+                    "internal decoding error: TokenId {} is not a symbol",
+                    id.0
+                )
+            }
+            Self::InvalidVocabulary(msg) => write!(f, "invalid vocabulary: {msg}"),
+            Self::InvalidContainer(msg) => write!(f, "invalid compressed file: {msg}"),
+            Self::InvalidStream(msg) => write!(f, "invalid token stream: {msg}"),
+            Self::File { path, source } => write!(f, "{}: {source}", path.display()),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Json(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::File { source, .. } => Some(source),
+            Self::Io(e) => Some(e),
+            Self::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
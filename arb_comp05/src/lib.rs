@@ -1,9 +1,34 @@
 pub mod bpe;
+pub mod config;
+pub mod container;
+pub mod corpus;
 pub mod diff;
+pub mod dot;
+pub mod entropy;
+pub mod error;
 pub mod matcher;
+pub mod metrics;
+mod pair_index;
 mod pairs;
+mod parallel;
+pub mod pretokenize;
+pub mod prune;
 pub mod recode;
+pub mod relative;
+pub mod report;
+pub mod sample;
+pub mod symbol;
 mod techniques;
 pub mod test_utils;
 mod token;
+pub mod token_set;
+pub mod tokenizer;
+#[cfg(test)]
 mod utils;
+mod varint;
+mod vocab;
+pub mod vocab_file;
+
+pub use error::Error;
+pub use symbol::Symbol;
+pub use token::{Token, TokenId};
@@ -1,36 +1,511 @@
 pub mod bpe;
+pub mod config;
+pub mod container;
+pub mod corpus;
+pub mod dot;
+pub mod entropy;
+pub mod error;
 pub mod matcher;
+pub mod metrics;
+mod pair_index;
 mod pairs;
+mod parallel;
+pub mod pretokenize;
+pub mod prune;
 pub mod recode;
+pub mod relative;
+pub mod report;
+pub mod sample;
+pub mod symbol;
 mod techniques;
 pub mod test_utils;
 mod token;
+pub mod token_set;
+pub mod tokenizer;
+#[cfg(test)]
 mod utils;
+mod varint;
+mod vocab;
+pub mod vocab_file;
 
-use bpe::Bpe;
+use bpe::{Bpe, EncodeMode};
+use config::{BpeConfig, Weighting};
+use container::Coding;
+use corpus::read_corpus;
+use error::Error;
 use matcher::greedy00;
+use pretokenize::Boundaries;
+use sample::{Sampling, TrainProgress};
+use std::fmt;
+use std::process::ExitCode;
 use test_utils::print_ui_01;
+use token::TokenId;
+use tokenizer::{BpeTokenizer, FixedChunks, GearChunks, Tokenizer};
+use vocab_file::VocabFormat;
 
-fn main() {
-    println!("diff test");
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Diff two files, with a vocabulary trained on them or loaded with `--vocab`.
+    Diff,
+    /// Train a vocabulary on a corpus and save it.
+    Train,
+    /// Write the merge tree of a vocabulary as Graphviz DOT.
+    Dot,
+    /// Store a file as a vocabulary and token stream (see `container`).
+    Compress,
+    /// Restore a file stored by `Compress`.
+    Decompress,
+    /// Print size and complexity metrics for files.
+    Metrics,
+}
+
+struct Args {
+    command: Command,
+    files: Vec<String>,
+    config: BpeConfig,
+    mode: EncodeMode,
+    report: bool,
+    vocab: Option<String>,
+    output: Option<String>,
+    format: VocabFormat,
+    roots: Option<Vec<TokenId>>,
+    coding: Coding,
+    /// Train on a sample of the corpus (see `Bpe::from_samples`).
+    sampling: Option<Sampling>,
+    /// Diff with chunks instead of BPE tokens.
+    chunking: Option<Chunking>,
+}
+
+/// A `Tokenizer` that needs no training, chosen for `Diff`
+#[derive(Debug, Clone, Copy)]
+enum Chunking {
+    Fixed(usize),
+    Gear(usize),
+}
+
+const USAGE: &str =
+    "usage: arb_comp05 [options] [--vocab FILE | --fixed N | --gear N] <file1> <file2>
+       arb_comp05 train [options] [--json] [--sample] [--window N] [--memory-mb N] [--seed N]
+                        --output FILE <corpus file or directory>...
+       arb_comp05 dot [options] [--roots ID,ID,...] [--output FILE] (--vocab FILE | <corpus>...)
+       arb_comp05 compress [options] [--vocab FILE] [--huffman] --output FILE <file>
+       arb_comp05 decompress --output FILE <file>
+       arb_comp05 metrics [options] [--vocab FILE] <file>...
+options: [--max-vocab N] [--min-freq N] [--min-inputs N] [--max-token-len N] [--max-merges N]
+         [--min-run N] [--per-input | --weights N,N,...]
+         [--every N | --newlines | --zero-runs | --boundaries N,N,...] [--ranked] [--report]";
+
+/// Options that only apply when diffing or training with BPE
+const BPE_OPTIONS: &[&str] = &[
+    "--vocab",
+    "--report",
+    "--ranked",
+    "--max-vocab",
+    "--min-freq",
+    "--min-inputs",
+    "--per-input",
+    "--weights",
+    "--max-token-len",
+    "--max-merges",
+    "--min-run",
+    "--every",
+    "--newlines",
+    "--zero-runs",
+    "--boundaries",
+];
+
+/// An error of the command line tool: bad arguments, or a failure in the library
+#[derive(Debug)]
+enum CliError {
+    /// Invalid command line arguments
+    Usage(String),
+    Lib(Error),
+}
+
+type CliResult<T> = std::result::Result<T, CliError>;
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage(msg) => write!(f, "{msg}"),
+            Self::Lib(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<Error> for CliError {
+    fn from(e: Error) -> Self {
+        Self::Lib(e)
+    }
+}
+
+/// Splits command line arguments into file names and options.
+fn parse_args(args: impl IntoIterator<Item = String>) -> CliResult<Args> {
+    let mut command = Command::Diff;
+    let mut files = vec![];
+    let mut config = BpeConfig::new();
+    let mut mode = EncodeMode::Greedy;
+    let mut report = false;
+    let mut vocab = None;
+    let mut output = None;
+    let mut format = VocabFormat::Binary;
+    let mut roots = None;
+    let mut coding = Coding::Varint;
+    let mut sampling = None::<Sampling>;
+    let mut chunking = None;
+    let mut bpe_option = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if BPE_OPTIONS.contains(&arg.as_str()) {
+            bpe_option.get_or_insert_with(|| arg.clone());
+        }
+        let mut value = || -> CliResult<String> {
+            args.next()
+                .ok_or_else(|| CliError::Usage(format!("{arg} requires a value\n{USAGE}")))
+        };
+        let number = |x: &str| -> CliResult<usize> {
+            x.parse()
+                .map_err(|_| CliError::Usage(format!("{arg} requires a number\n{USAGE}")))
+        };
+
+        config = match arg.as_str() {
+            "--max-vocab" => config.max_vocab_size(number(&value()?)?),
+            "--min-freq" => config.min_pair_frequency(number(&value()?)?),
+            "--min-inputs" => config.min_inputs(number(&value()?)?),
+            "--per-input" => config.weighting(Weighting::PerInput),
+            "--weights" => {
+                let weights = value()?
+                    .split(',')
+                    .map(number)
+                    .collect::<CliResult<Vec<_>>>()?;
+                config.weighting(Weighting::Custom(
+                    weights.into_iter().map(|x| x as u64).collect(),
+                ))
+            }
+            "--max-token-len" => config.max_token_len(number(&value()?)?),
+            "--max-merges" => config.max_merges(number(&value()?)?),
+            "--min-run" => config.min_run_len(number(&value()?)?),
+            "--every" => config.boundaries(Boundaries::EveryN(number(&value()?)?)),
+            "--newlines" => config.boundaries(Boundaries::Newlines),
+            "--zero-runs" => config.boundaries(Boundaries::ZeroRuns),
+            "--boundaries" => {
+                let positions = value()?.split(',').map(number).collect::<CliResult<_>>()?;
+                config.boundaries(Boundaries::Positions(positions))
+            }
+            "--ranked" => {
+                mode = EncodeMode::Ranked;
+                config
+            }
+            "--report" => {
+                report = true;
+                config
+            }
+            "--vocab" => {
+                vocab = Some(value()?);
+                config
+            }
+            "--output" => {
+                output = Some(value()?);
+                config
+            }
+            "--json" => {
+                format = VocabFormat::Json;
+                config
+            }
+            "--roots" => {
+                let ids = value()?
+                    .split(',')
+                    .map(number)
+                    .collect::<CliResult<Vec<_>>>()?;
+                roots = Some(ids.into_iter().map(TokenId).collect());
+                config
+            }
+            "train" if command == Command::Diff && files.is_empty() => {
+                command = Command::Train;
+                config
+            }
+            "dot" if command == Command::Diff && files.is_empty() => {
+                command = Command::Dot;
+                config
+            }
+            "compress" if command == Command::Diff && files.is_empty() => {
+                command = Command::Compress;
+                config
+            }
+            "decompress" if command == Command::Diff && files.is_empty() => {
+                command = Command::Decompress;
+                config
+            }
+            "metrics" if command == Command::Diff && files.is_empty() => {
+                command = Command::Metrics;
+                config
+            }
+            "--huffman" => {
+                coding = Coding::Huffman;
+                config
+            }
+            "--fixed" => {
+                chunking = Some(Chunking::Fixed(number(&value()?)?));
+                config
+            }
+            "--gear" => {
+                chunking = Some(Chunking::Gear(number(&value()?)?));
+                config
+            }
+            "--sample" => {
+                sampling = Some(sampling.unwrap_or_default());
+                config
+            }
+            "--window" => {
+                sampling = Some(sampling.unwrap_or_default().window_len(number(&value()?)?));
+                config
+            }
+            "--memory-mb" => {
+                let bytes = number(&value()?)?.saturating_mul(1 << 20);
+                sampling = Some(sampling.unwrap_or_default().memory_budget(bytes));
+                config
+            }
+            "--seed" => {
+                let seed = number(&value()?)? as u64;
+                sampling = Some(sampling.unwrap_or_default().seed(seed));
+                config
+            }
+            _ => {
+                files.push(arg);
+                config
+            }
+        };
+    }
+
+    if let Some(chunking) = chunking {
+        let chunk_option = match chunking {
+            Chunking::Fixed(_) => "--fixed",
+            Chunking::Gear(_) => "--gear",
+        };
+        if command != Command::Diff {
+            return Err(CliError::Usage(format!(
+                "{chunk_option} only applies to diff\n{USAGE}"
+            )));
+        }
+        if let Some(option) = bpe_option {
+            return Err(CliError::Usage(format!(
+                "{chunk_option} cannot be combined with {option}\n{USAGE}"
+            )));
+        }
+    }
+
+    Ok(Args {
+        command,
+        files,
+        config,
+        mode,
+        report,
+        vocab,
+        output,
+        format,
+        roots,
+        coding,
+        sampling,
+        chunking,
+    })
+}
+
+fn read_file(path: &str) -> CliResult<Vec<u8>> {
+    Ok(std::fs::read(path).map_err(Error::file(path))?)
+}
+
+fn train(args: Args) -> CliResult<()> {
+    let Some(output) = args.output else {
+        return Err(CliError::Usage(format!("train requires --output\n{USAGE}")));
+    };
+    if args.files.is_empty() {
+        return Err(CliError::Usage(format!("train requires a corpus\n{USAGE}")));
+    }
+
+    let bpe = match &args.sampling {
+        Some(sampling) => {
+            let corpus = read_corpus(&args.files)?;
+            let corpus = corpus.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            Bpe::from_samples(&corpus, &args.config, sampling, print_progress)
+        }
+        None => Bpe::from_corpus(&args.files, &args.config)?,
+    };
+    if args.report {
+        print!("{}", bpe.report());
+    }
+    bpe.save(&output, args.format)?;
+    println!("saved {} tokens to {output}", bpe.tokens().len());
+    Ok(())
+}
 
-    //read files from first 2 arguments
-    let args: Vec<String> = std::env::args().collect();
-    let files = args
+fn print_progress(progress: TrainProgress) {
+    match progress {
+        TrainProgress::Sampled { windows, symbols } => {
+            eprintln!("sampled {symbols} bytes in {windows} windows")
+        }
+        TrainProgress::Merged { merges } if merges % 1000 == 0 => eprintln!("{merges} merges"),
+        TrainProgress::Merged { .. } => {}
+        TrainProgress::Encoded { inputs } => eprintln!("encoded {inputs} files"),
+    }
+}
+
+fn dot(args: Args) -> CliResult<()> {
+    let bpe = match &args.vocab {
+        Some(path) => Bpe::load(path)?,
+        None if !args.files.is_empty() => Bpe::from_corpus(&args.files, &args.config)?,
+        None => {
+            return Err(CliError::Usage(format!(
+                "dot requires --vocab or a corpus\n{USAGE}"
+            )))
+        }
+    };
+
+    let dot = bpe.to_dot(args.roots.as_deref())?;
+    match &args.output {
+        Some(path) => std::fs::write(path, dot).map_err(Error::file(path))?,
+        None => print!("{dot}"),
+    }
+    Ok(())
+}
+
+/// Returns the single input file and the `--output` file of a subcommand.
+fn input_and_output<'a>(args: &'a Args, command: &str) -> CliResult<(&'a str, &'a str)> {
+    let Some(output) = &args.output else {
+        return Err(CliError::Usage(format!(
+            "{command} requires --output\n{USAGE}"
+        )));
+    };
+    let [input] = &args.files[..] else {
+        return Err(CliError::Usage(format!(
+            "{command} requires one input file\n{USAGE}"
+        )));
+    };
+    Ok((input, output))
+}
+
+fn compress(args: Args) -> CliResult<()> {
+    let (input, output) = input_and_output(&args, "compress")?;
+    let data = read_file(input)?;
+
+    let compressed = match &args.vocab {
+        Some(path) => container::compress_with_coded(&Bpe::load(path)?, &data, args.coding),
+        None => container::compress_coded(&data, &args.config, args.coding),
+    };
+    std::fs::write(output, &compressed).map_err(Error::file(output))?;
+
+    println!(
+        "{} bytes -> {} bytes ({:.2}x)",
+        data.len(),
+        compressed.len(),
+        data.len() as f64 / compressed.len() as f64
+    );
+    Ok(())
+}
+
+fn decompress(args: Args) -> CliResult<()> {
+    let (input, output) = input_and_output(&args, "decompress")?;
+    let data = container::decompress(&read_file(input)?)?;
+    std::fs::write(output, data).map_err(Error::file(output))?;
+    Ok(())
+}
+
+fn metrics(args: Args) -> CliResult<()> {
+    if args.files.is_empty() {
+        return Err(CliError::Usage(format!("metrics requires a file\n{USAGE}")));
+    }
+    let data = args
+        .files
         .iter()
-        .skip(1)
-        .take(2)
-        .map(|s| s.to_string())
-        .collect::<Vec<_>>();
-    let file1 = std::fs::read(&files[0]).expect("Could not read file");
-    let file2 = std::fs::read(&files[1]).expect("Could not read file");
+        .map(|file| read_file(file))
+        .collect::<CliResult<Vec<_>>>()?;
+    let data = data.iter().map(Vec::as_slice).collect::<Vec<_>>();
 
-    let bpe = Bpe::new(&[&file1, &file2]);
+    let bpe = match &args.vocab {
+        Some(path) => Bpe::load(path)?,
+        None => Bpe::with_config(&data, &args.config),
+    };
 
-    let ids0 = bpe.encode(&file1);
-    let ids1 = bpe.encode(&file2);
+    for (file, m) in args
+        .files
+        .iter()
+        .zip(metrics::measure_all(&bpe, &data, args.mode))
+    {
+        println!(
+            "{file}: {} bytes, {} tokens, {} bytes as varints, {} bytes entropy coded \
+             (bound {:.0}), {:.3} bits/byte",
+            m.raw_bytes,
+            m.tokens,
+            m.varint_bytes,
+            m.entropy_coded_bytes,
+            m.entropy_bits / 8.0,
+            m.bits_per_byte()
+        );
+    }
+    if let [a, b] = data[..] {
+        let similarity = metrics::token_similarity(&bpe, a, b, args.mode);
+        println!("token similarity: {similarity:.3}");
+    }
+    Ok(())
+}
+
+fn diff(args: Args) -> CliResult<()> {
+    println!("diff test");
+
+    //read files from the first 2 non-option arguments
+    let [file1, file2] = &args.files[..] else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+    let file1 = read_file(file1)?;
+    let file2 = read_file(file2)?;
+
+    let bpe;
+    let mut tokenizer: Box<dyn Tokenizer> = match args.chunking {
+        Some(Chunking::Fixed(width)) => Box::new(FixedChunks::new(width)),
+        Some(Chunking::Gear(avg_len)) => Box::new(GearChunks::new(avg_len)),
+        None => {
+            bpe = match &args.vocab {
+                Some(path) => Bpe::load(path)?,
+                None => Bpe::with_config(&[&file1, &file2], &args.config),
+            };
+            if args.report {
+                print!("{}", bpe.report());
+            }
+            Box::new(BpeTokenizer {
+                bpe: &bpe,
+                mode: args.mode,
+            })
+        }
+    };
+
+    let ids0 = tokenizer.encode(&file1);
+    let ids1 = tokenizer.encode(&file2);
 
     let matches = greedy00(&ids0, &ids1);
 
-    print_ui_01(&matches, |x| bpe.decode(x.clone()), false);
+    print_ui_01(&matches, |x| tokenizer.decode(x), false);
+    Ok(())
+}
+
+fn run() -> CliResult<()> {
+    let args = parse_args(std::env::args().skip(1))?;
+
+    match args.command {
+        Command::Diff => diff(args),
+        Command::Train => train(args),
+        Command::Dot => dot(args),
+        Command::Compress => compress(args),
+        Command::Decompress => decompress(args),
+        Command::Metrics => metrics(args),
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,139 @@
+//! Size and complexity metrics for inputs encoded with a [`Bpe`]
+
+use crate::bpe::{Bpe, EncodeMode};
+use crate::entropy;
+use crate::token::TokenId;
+use crate::varint::write_varint;
+use std::collections::BTreeSet;
+
+/// Sizes of one input and its encodings
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub raw_bytes: usize,
+    pub tokens: usize,
+    /// The token stream with each `TokenId` as a varint
+    pub varint_bytes: usize,
+    /// The token stream Huffman coded by [`entropy::encode`], including its code table
+    pub entropy_coded_bytes: usize,
+    /// The order-0 entropy of the token stream: a lower bound for the coded stream
+    pub entropy_bits: f64,
+}
+
+impl Metrics {
+    /// Entropy-coded bits per input byte (0 for an empty input).
+    pub fn bits_per_byte(&self) -> f64 {
+        if self.raw_bytes == 0 {
+            0.0
+        } else {
+            8.0 * self.entropy_coded_bytes as f64 / self.raw_bytes as f64
+        }
+    }
+
+    /// Input bytes per entropy-coded byte.
+    pub fn compression_ratio(&self) -> f64 {
+        self.raw_bytes as f64 / self.entropy_coded_bytes as f64
+    }
+}
+
+/// Encodes `data` with `bpe` and measures the result.
+pub fn measure(bpe: &Bpe, data: &[u8], mode: EncodeMode) -> Metrics {
+    metrics(data.len(), &bpe.encode_with(data, mode))
+}
+
+/// [`measure`] for several inputs, encoded in parallel with the `parallel` feature.
+pub fn measure_all(bpe: &Bpe, data: &[&[u8]], mode: EncodeMode) -> Vec<Metrics> {
+    data.iter()
+        .zip(bpe.encode_all(data, mode))
+        .map(|(x, ids)| metrics(x.len(), &ids))
+        .collect()
+}
+
+/// Similarity of two inputs by the tokens they are encoded with, from 0 (no tokens in
+/// common) to 1 (the same tokens, in any order).
+///
+/// This is the weighted Jaccard index of the two token histograms, with each token weighted
+/// by its byte length: roughly the fraction of bytes covered by tokens the inputs share.
+/// It is most telling with a vocabulary trained on both inputs, as used for their diff.
+pub fn token_similarity(bpe: &Bpe, a: &[u8], b: &[u8], mode: EncodeMode) -> f64 {
+    let counts_a = entropy::id_counts(&bpe.encode_with(a, mode));
+    let counts_b = entropy::id_counts(&bpe.encode_with(b, mode));
+
+    let mut shared = 0;
+    let mut total = 0;
+    for id in counts_a
+        .keys()
+        .chain(counts_b.keys())
+        .collect::<BTreeSet<_>>()
+    {
+        let count_a = counts_a.get(id).copied().unwrap_or(0);
+        let count_b = counts_b.get(id).copied().unwrap_or(0);
+        let len = bpe.token_len(*id).unwrap_or(0);
+        shared += count_a.min(count_b) * len;
+        total += count_a.max(count_b) * len;
+    }
+
+    if total == 0 {
+        1.0
+    } else {
+        shared as f64 / total as f64
+    }
+}
+
+fn metrics(raw_bytes: usize, ids: &[TokenId]) -> Metrics {
+    let mut varints = vec![];
+    ids.iter()
+        .for_each(|id| write_varint(&mut varints, id.0 as u64));
+
+    Metrics {
+        raw_bytes,
+        tokens: ids.len(),
+        varint_bytes: varints.len(),
+        entropy_coded_bytes: entropy::encode(ids).len(),
+        entropy_bits: entropy::entropy_bits(ids),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_measure() {
+        let data = b"abcdabcdabcdabcd".repeat(50);
+        let bpe = Bpe::new(&[&data]);
+
+        let metrics = measure(&bpe, &data, EncodeMode::Ranked);
+        assert_eq!(metrics.raw_bytes, 800);
+        assert_eq!(metrics.tokens, bpe.encode_ranked(&data).len());
+        assert!(metrics.tokens < 100);
+        assert!(metrics.varint_bytes >= metrics.tokens);
+        assert!(metrics.entropy_bits <= 8.0 * metrics.entropy_coded_bytes as f64);
+        assert!(metrics.bits_per_byte() < 1.0);
+        assert!(metrics.compression_ratio() > 8.0);
+
+        let all = measure_all(&bpe, &[&data, b""], EncodeMode::Ranked);
+        assert_eq!(all[0], metrics);
+        assert_eq!(all[1].tokens, 0);
+        assert_eq!(all[1].bits_per_byte(), 0.0);
+    }
+
+    #[test]
+    fn test_token_similarity() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(13);
+        let mut random = || (0..2000).map(|_| rng.gen_range(0..16)).collect::<Vec<u8>>();
+        let a = random();
+        let mut similar = a.clone();
+        similar[1000] ^= 1;
+        let b = random();
+
+        let bpe = Bpe::new(&[&a, &similar, &b]);
+        let similarity = |x: &[u8], y: &[u8]| token_similarity(&bpe, x, y, EncodeMode::Ranked);
+
+        assert_eq!(similarity(&a, &a), 1.0);
+        assert_eq!(similarity(b"", b""), 1.0);
+        assert_eq!(similarity(b"x", b"y"), 0.0);
+        assert!(similarity(&a, &similar) > 0.9);
+        assert!(similarity(&a, &b) < similarity(&a, &similar));
+    }
+}
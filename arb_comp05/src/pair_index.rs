@@ -0,0 +1,365 @@
+//! Incrementally maintained pair counts for BPE training
+
+use crate::parallel;
+use crate::token::TokenId;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+
+type Pair = (TokenId, TokenId);
+
+/// Location of a pair in the training patterns: (pattern index, index of the pair's first node).
+///
+/// Node indices never change when neighbouring nodes are merged away, so ordering positions
+/// matches ordering by first appearance in the concatenated patterns.
+type Position = (usize, usize);
+
+const NONE: usize = usize::MAX;
+
+/// Number of pair starts counted per work item when building the index.
+const COUNT_CHUNK: usize = if cfg!(test) { 16 } else { 1 << 16 };
+
+/// A change to the occurrence sets, recorded while merging a single pattern.
+enum Edit {
+    Add(Pair, Position),
+    Remove(Pair, Position),
+}
+
+/// A pattern stored as a doubly linked list over its original node indices.
+struct Sequence {
+    ids: Vec<Option<TokenId>>,
+    prev: Vec<usize>,
+    next: Vec<usize>,
+}
+
+impl Sequence {
+    fn new(ids: Vec<TokenId>) -> Self {
+        let len = ids.len();
+        Self {
+            ids: ids.into_iter().map(Some).collect(),
+            prev: (0..len).map(|i| i.checked_sub(1).unwrap_or(NONE)).collect(),
+            next: (0..len)
+                .map(|i| if i + 1 < len { i + 1 } else { NONE })
+                .collect(),
+        }
+    }
+
+    fn id(&self, node: usize) -> Option<TokenId> {
+        self.ids.get(node).copied().flatten()
+    }
+
+    fn to_ids(&self) -> Vec<TokenId> {
+        self.ids.iter().flatten().copied().collect()
+    }
+
+    /// Counts the pairs starting at nodes `start..start + COUNT_CHUNK` of pattern `p`.
+    fn count_pairs(&self, p: usize, start: usize) -> HashMap<Pair, BTreeSet<Position>> {
+        let end = std::cmp::min(start + COUNT_CHUNK + 1, self.ids.len());
+        let mut counts = HashMap::<Pair, BTreeSet<Position>>::new();
+
+        for (i, ids) in self.ids[start..end].windows(2).enumerate() {
+            if let [Some(id0), Some(id1)] = *ids {
+                counts.entry((id0, id1)).or_default().insert((p, start + i));
+            }
+        }
+        counts
+    }
+
+    /// Merges `pair` at each of `nodes` (sorted) in pattern `p`,
+    /// returning the occurrence changes in the order they happen.
+    fn merge(&mut self, p: usize, nodes: &[usize], pair: Pair, merged: TokenId) -> Vec<Edit> {
+        let mut edits = vec![];
+
+        for &i in nodes {
+            let j = self.next[i];
+            if self.id(i) != Some(pair.0) || self.id(j) != Some(pair.1) {
+                // consumed by an overlapping occurrence earlier in this merge
+                continue;
+            }
+            let h = self.prev[i];
+            let n = self.next[j];
+
+            if let Some(id) = self.id(h) {
+                edits.push(Edit::Remove((id, pair.0), (p, h)));
+            }
+            if let Some(id) = self.id(n) {
+                edits.push(Edit::Remove((pair.1, id), (p, j)));
+            }
+
+            self.ids[i] = Some(merged);
+            self.ids[j] = None;
+            self.next[i] = n;
+            if n != NONE {
+                self.prev[n] = i;
+            }
+
+            if let Some(id) = self.id(h) {
+                edits.push(Edit::Add((id, merged), (p, h)));
+            }
+            if let Some(id) = self.id(n) {
+                edits.push(Edit::Add((merged, id), (p, i)));
+            }
+        }
+        edits
+    }
+}
+
+/// Adjacent pair counts over a set of patterns, updated in place as pairs are merged.
+///
+/// Selection matches a full recount with [`crate::token::find_most_common_duplicate_id_pair`]:
+/// the pair with the highest count wins, and ties go to the pair whose first occurrence
+/// is latest in the concatenated patterns. With weights, an occurrence counts as the weight
+/// of its pattern.
+pub struct PairIndex {
+    sequences: Vec<Sequence>,
+    weights: Vec<u64>,
+    occurrences: HashMap<Pair, BTreeSet<Position>>,
+    /// Sum of the weights of each pair's occurrences.
+    scores: HashMap<Pair, u64>,
+    queue: BinaryHeap<(u64, Position, Pair)>,
+}
+
+impl PairIndex {
+    /// An index where each occurrence in `patterns[p]` counts `weights[p]` times.
+    pub fn new(patterns: Vec<Vec<TokenId>>, weights: Vec<u64>) -> Self {
+        let mut index = Self {
+            sequences: patterns.into_iter().map(Sequence::new).collect(),
+            weights,
+            occurrences: HashMap::new(),
+            scores: HashMap::new(),
+            queue: BinaryHeap::new(),
+        };
+
+        let chunks = index
+            .sequences
+            .iter()
+            .enumerate()
+            .flat_map(|(p, sequence)| {
+                (0..sequence.ids.len())
+                    .step_by(COUNT_CHUNK)
+                    .map(move |start| (p, sequence, start))
+            })
+            .collect::<Vec<_>>();
+        let counts = parallel::map(chunks, |(p, sequence, start)| {
+            sequence.count_pairs(p, start)
+        });
+
+        for chunk in counts {
+            for (pair, mut positions) in chunk {
+                index
+                    .occurrences
+                    .entry(pair)
+                    .or_default()
+                    .append(&mut positions);
+            }
+        }
+
+        for (&pair, positions) in &index.occurrences {
+            let score = positions.iter().map(|&(p, _)| index.weights[p]).sum();
+            index.scores.insert(pair, score);
+        }
+
+        let pairs = index.occurrences.keys().copied().collect::<Vec<_>>();
+        pairs.into_iter().for_each(|pair| index.enqueue(pair));
+
+        index
+    }
+
+    /// Removes and returns the most common (highest weighted) pair and its number of occurrences.
+    ///
+    /// The pair's occurrences stay in the index until it is merged.
+    pub fn pop_most_common(&mut self) -> Option<(Pair, usize)> {
+        while let Some((score, first, pair)) = self.queue.pop() {
+            let current = self.occurrences.get(&pair);
+            if self.scores.get(&pair) == Some(&score)
+                && current.and_then(BTreeSet::first) == Some(&first)
+            {
+                return Some((pair, current.map_or(0, BTreeSet::len)));
+            }
+        }
+        None
+    }
+
+    /// Replaces every non-overlapping occurrence of `pair` (scanning left to right) with `merged`.
+    pub fn merge(&mut self, pair: Pair, merged: TokenId) {
+        let Some(positions) = self.occurrences.remove(&pair) else {
+            return;
+        };
+        self.scores.remove(&pair);
+        let mut groups: Vec<(usize, Vec<usize>)> = vec![];
+        for (p, i) in positions {
+            match groups.last_mut() {
+                Some((q, nodes)) if *q == p => nodes.push(i),
+                _ => groups.push((p, vec![i])),
+            }
+        }
+
+        // patterns are merged independently; their edits are applied in pattern order.
+        // groups are sorted by pattern, so each one splits its sequence off the rest
+        let mut work = Vec::with_capacity(groups.len());
+        let mut rest = &mut self.sequences[..];
+        let mut start = 0;
+        for (p, nodes) in groups {
+            let (sequence, tail) = std::mem::take(&mut rest)[p - start..]
+                .split_first_mut()
+                .unwrap();
+            work.push((p, sequence, nodes));
+            rest = tail;
+            start = p + 1;
+        }
+        let edits = parallel::map(work, |(p, sequence, nodes)| {
+            sequence.merge(p, &nodes, pair, merged)
+        });
+
+        let mut touched = HashSet::new();
+        for edit in edits.into_iter().flatten() {
+            match edit {
+                Edit::Add(pair, position) => self.add_occurrence(pair, position, &mut touched),
+                Edit::Remove(pair, position) => {
+                    self.remove_occurrence(pair, position, &mut touched)
+                }
+            }
+        }
+
+        let mut touched = touched.into_iter().collect::<Vec<_>>();
+        touched.sort();
+        touched.into_iter().for_each(|pair| self.enqueue(pair));
+    }
+
+    /// The number of distinct inputs `pair` occurs in, where pattern `p` belongs to input `input_of[p]`
+    /// and patterns of the same input are adjacent.
+    pub fn count_inputs(&self, pair: Pair, input_of: &[usize]) -> usize {
+        let positions = self.occurrences.get(&pair).into_iter().flatten();
+        let mut inputs = positions.map(|&(p, _)| input_of[p]).collect::<Vec<_>>();
+        inputs.dedup();
+        inputs.len()
+    }
+
+    /// Returns the current contents of each pattern.
+    pub fn into_patterns(self) -> Vec<Vec<TokenId>> {
+        self.sequences.iter().map(Sequence::to_ids).collect()
+    }
+
+    fn enqueue(&mut self, pair: Pair) {
+        if let Some(positions) = self.occurrences.get(&pair) {
+            if let Some(&first) = positions.first() {
+                self.queue.push((self.scores[&pair], first, pair));
+            }
+        }
+    }
+
+    fn add_occurrence(&mut self, pair: Pair, position: Position, touched: &mut HashSet<Pair>) {
+        if self.occurrences.entry(pair).or_default().insert(position) {
+            *self.scores.entry(pair).or_default() += self.weights[position.0];
+        }
+        touched.insert(pair);
+    }
+
+    fn remove_occurrence(&mut self, pair: Pair, position: Position, touched: &mut HashSet<Pair>) {
+        if let Some(positions) = self.occurrences.get_mut(&pair) {
+            if positions.remove(&position) {
+                *self.scores.get_mut(&pair).unwrap() -= self.weights[position.0];
+            }
+            if positions.is_empty() {
+                self.occurrences.remove(&pair);
+                self.scores.remove(&pair);
+            }
+            touched.insert(pair);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{find_most_common_duplicate_id_pair, merge};
+    use rand::{Rng, SeedableRng};
+
+    fn ids(x: &[usize]) -> Vec<TokenId> {
+        x.iter().map(|&x| TokenId(x)).collect()
+    }
+
+    #[test]
+    fn test_pair_index() {
+        let mut index = PairIndex::new(vec![ids(&[0, 0, 0]), ids(&[0, 1, 0, 1])], vec![1; 2]);
+
+        // tied: (0, 1) first occurs later than (0, 0)
+        assert_eq!(index.pop_most_common(), Some(((TokenId(0), TokenId(1)), 2)));
+
+        index.merge((TokenId(0), TokenId(1)), TokenId(2));
+        assert_eq!(index.pop_most_common(), Some(((TokenId(0), TokenId(0)), 2)));
+
+        index.merge((TokenId(0), TokenId(0)), TokenId(3));
+        assert_eq!(index.pop_most_common(), Some(((TokenId(2), TokenId(2)), 1)));
+        assert_eq!(index.pop_most_common(), Some(((TokenId(3), TokenId(0)), 1)));
+        assert_eq!(index.pop_most_common(), None);
+        assert_eq!(index.into_patterns(), vec![ids(&[3, 0]), ids(&[2, 2])]);
+    }
+
+    #[test]
+    fn test_pair_index_weights() {
+        let patterns = vec![ids(&[0, 1, 0, 1]), ids(&[2, 2, 0, 1])];
+        let mut index = PairIndex::new(patterns, vec![1, 3]);
+
+        // (2, 2) occurs once but weighs 3; (0, 1) weighs 1 + 1 + 3
+        assert_eq!(index.pop_most_common(), Some(((TokenId(0), TokenId(1)), 3)));
+        index.merge((TokenId(0), TokenId(1)), TokenId(3));
+        assert_eq!(index.pop_most_common(), Some(((TokenId(2), TokenId(3)), 1)));
+        assert_eq!(index.pop_most_common(), Some(((TokenId(2), TokenId(2)), 1)));
+        assert_eq!(index.pop_most_common(), Some(((TokenId(3), TokenId(3)), 1)));
+        assert_eq!(index.pop_most_common(), None);
+    }
+
+    #[test]
+    fn test_count_inputs() {
+        let index = PairIndex::new(
+            vec![ids(&[0, 1]), ids(&[0, 1, 0]), ids(&[1, 0, 1])],
+            vec![1; 3],
+        );
+        let pair = (TokenId(0), TokenId(1));
+        assert_eq!(index.count_inputs(pair, &[0, 1, 2]), 3);
+        assert_eq!(index.count_inputs(pair, &[0, 0, 1]), 2);
+        assert_eq!(index.count_inputs((TokenId(1), TokenId(0)), &[0, 0, 0]), 1);
+        assert_eq!(index.count_inputs((TokenId(1), TokenId(1)), &[0, 1, 2]), 0);
+    }
+
+    #[test]
+    fn test_pair_index_matches_recount() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        for _ in 0..20 {
+            let mut patterns = (0..3)
+                .map(|_| {
+                    let len = rng.gen_range(0..200);
+                    (0..len)
+                        .map(|_| TokenId(rng.gen_range(0..4)))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            let mut index = PairIndex::new(patterns.clone(), vec![1; 3]);
+            let mut next_id = 4;
+
+            loop {
+                let expected = find_most_common_duplicate_id_pair(patterns.iter());
+                let actual = index.pop_most_common().filter(|&(_, count)| count > 1);
+                assert_eq!(actual, expected);
+
+                let Some((pair, _)) = expected else {
+                    break;
+                };
+                let merged = TokenId(next_id);
+                next_id += 1;
+
+                index.merge(pair, merged);
+                patterns = patterns
+                    .iter()
+                    .map(|pattern| {
+                        merge(pattern.iter().copied(), |id0, id1| {
+                            Some(merged).filter(|_| (id0, id1) == pair)
+                        })
+                    })
+                    .collect();
+            }
+
+            assert_eq!(index.into_patterns(), patterns);
+        }
+    }
+}
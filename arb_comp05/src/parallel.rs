@@ -0,0 +1,20 @@
+//! Optional data parallelism (the `parallel` cargo feature)
+//!
+//! Work is split into independent items and results are returned in item order,
+//! so output is identical with or without the feature.
+
+/// Applies `f` to each item, on the rayon thread pool when the `parallel` feature is enabled.
+pub(crate) fn map<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    if items.len() > 1 {
+        use rayon::prelude::*;
+        return items.into_par_iter().map(f).collect();
+    }
+
+    items.into_iter().map(f).collect()
+}
@@ -0,0 +1,191 @@
+//! Pre-tokenization: boundaries that BPE merges may not cross
+
+use crate::symbol::Symbol;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// A rule for placing merge boundaries in an input
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Boundaries {
+    /// Merges may span the whole input.
+    #[default]
+    None,
+    /// Boundaries at fixed symbol offsets, the same in every input.
+    Positions(Vec<usize>),
+    /// A boundary every `n` symbols, for fixed-size records.
+    EveryN(usize),
+    /// A boundary after each `\n` (a symbol with value 10).
+    Newlines,
+    /// Boundaries at the start and end of each run of zero symbols.
+    ZeroRuns,
+}
+
+impl Boundaries {
+    /// Returns the boundary offsets in `data`, sorted, excluding `0` and `data.len()`.
+    pub fn split_points<S: Symbol>(&self, data: &[S]) -> Vec<usize> {
+        let inside = |&i: &usize| 0 < i && i < data.len();
+
+        match self {
+            Self::None => vec![],
+            Self::Positions(positions) => {
+                let mut points = positions.iter().copied().filter(inside).collect::<Vec<_>>();
+                points.sort_unstable();
+                points.dedup();
+                points
+            }
+            Self::EveryN(0) => vec![],
+            Self::EveryN(n) => (*n..data.len()).step_by(*n).collect(),
+            Self::Newlines => data
+                .iter()
+                .enumerate()
+                .filter(|&(_, &x)| x.to_u64() == u64::from(b'\n'))
+                .map(|(i, _)| i + 1)
+                .filter(inside)
+                .collect(),
+            Self::ZeroRuns => (1..data.len())
+                .filter(|&i| (data[i - 1].to_u64() == 0) != (data[i].to_u64() == 0))
+                .collect(),
+        }
+    }
+
+    /// Splits `0..data.len()` into the ranges between boundaries.
+    pub fn segments<S: Symbol>(&self, data: &[S]) -> Vec<Range<usize>> {
+        if data.is_empty() {
+            return vec![];
+        }
+
+        let mut start = 0;
+        let mut segments = vec![];
+        for point in self.split_points(data) {
+            segments.push(start..point);
+            start = point;
+        }
+        segments.push(start..data.len());
+        segments
+    }
+
+    /// Splits `window` of `data` into the ranges between boundaries, placed as in the whole
+    /// of `data`, plus the window's edges.
+    pub fn segments_in<S: Symbol>(&self, data: &[S], window: Range<usize>) -> Vec<Range<usize>> {
+        let offset = window.start;
+        let local = match self {
+            Self::Positions(positions) => Self::Positions(
+                (positions.iter())
+                    .filter_map(|&point| point.checked_sub(offset))
+                    .collect(),
+            ),
+            Self::EveryN(n) if *n > 0 => {
+                Self::Positions((n - offset % n..window.len()).step_by(*n).collect())
+            }
+            rule => rule.clone(),
+        };
+
+        (local.segments(&data[window]).into_iter())
+            .map(|range| range.start + offset..range.end + offset)
+            .collect()
+    }
+}
+
+/// Splits `range` of `data` around each run of at least `min_len` equal symbols,
+/// returning the pieces in order with whether each is such a run.
+pub fn split_runs<S: PartialEq>(
+    data: &[S],
+    range: Range<usize>,
+    min_len: usize,
+) -> Vec<(Range<usize>, bool)> {
+    let mut pieces = vec![];
+    let mut start = range.start;
+    let mut i = range.start;
+
+    while i < range.end {
+        let run_end = (i..range.end)
+            .find(|&j| data[j] != data[i])
+            .unwrap_or(range.end);
+        if run_end - i >= min_len {
+            if start < i {
+                pieces.push((start..i, false));
+            }
+            pieces.push((i..run_end, true));
+            start = run_end;
+        }
+        i = run_end;
+    }
+    if start < range.end {
+        pieces.push((start..range.end, false));
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_points() {
+        let data = b"ab\ncd\n\0\0\0e\n";
+
+        assert_eq!(Boundaries::None.split_points(data), Vec::<usize>::new());
+        assert_eq!(
+            Boundaries::Positions(vec![20, 4, 0, 2, 4]).split_points(data),
+            vec![2, 4]
+        );
+        assert_eq!(Boundaries::EveryN(4).split_points(data), vec![4, 8]);
+        assert_eq!(
+            Boundaries::EveryN(0).split_points(data),
+            Vec::<usize>::new()
+        );
+        assert_eq!(Boundaries::Newlines.split_points(data), vec![3, 6]);
+        assert_eq!(Boundaries::ZeroRuns.split_points(data), vec![6, 9]);
+        assert_eq!(Boundaries::ZeroRuns.split_points(b"\0\0a"), vec![2]);
+        assert_eq!(
+            Boundaries::Newlines.split_points(&['a', '\n', 'é']),
+            vec![2]
+        );
+        assert_eq!(Boundaries::ZeroRuns.split_points(&[7u16, 0, 0]), vec![1]);
+    }
+
+    #[test]
+    fn test_split_runs() {
+        let data = b"ab\0\0\0\0cdd\xff\xff\xff";
+        assert_eq!(
+            split_runs(data, 0..data.len(), 3),
+            vec![(0..2, false), (2..6, true), (6..9, false), (9..12, true)]
+        );
+        assert_eq!(split_runs(data, 3..8, 3), vec![(3..6, true), (6..8, false)]);
+        assert_eq!(split_runs(data, 0..4, 3), vec![(0..4, false)]);
+        assert_eq!(split_runs(data, 5..5, 3), vec![]);
+    }
+
+    #[test]
+    fn test_segments() {
+        assert_eq!(
+            Boundaries::Newlines.segments(b""),
+            Vec::<Range<usize>>::new()
+        );
+        assert_eq!(Boundaries::None.segments(b"abc"), vec![0..3]);
+        assert_eq!(
+            Boundaries::Newlines.segments(b"a\nbc\nd"),
+            vec![0..2, 2..5, 5..6]
+        );
+
+        let data = b"abcdefgh\nij";
+        for boundaries in [
+            Boundaries::None,
+            Boundaries::Positions(vec![2, 5, 6, 20]),
+            Boundaries::EveryN(3),
+            Boundaries::Newlines,
+        ] {
+            for window in [0..11, 1..11, 3..9, 4..5, 7..7] {
+                let expected = (boundaries.segments(data).into_iter())
+                    .map(|range| range.start.max(window.start)..range.end.min(window.end))
+                    .filter(|range| !range.is_empty())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    boundaries.segments_in(data, window.clone()),
+                    expected,
+                    "{boundaries:?} {window:?}"
+                );
+            }
+        }
+    }
+}
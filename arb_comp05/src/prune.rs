@@ -0,0 +1,320 @@
+//! Removing merges that training only used as stepping stones (see [`crate::bpe::Bpe::prune`])
+
+use crate::error::{Error, Result};
+use crate::symbol::Symbol;
+use crate::token::{Token, TokenId};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Translates `TokenId`s from before a [`crate::bpe::Bpe::prune`] to the pruned vocabulary.
+///
+/// A kept token maps to its new id; a removed token maps to the kept tokens that spell it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenRemap {
+    map: Vec<Vec<TokenId>>,
+}
+
+impl TokenRemap {
+    pub(crate) fn identity(len: usize) -> Self {
+        Self {
+            map: (0..len).map(|i| vec![TokenId(i)]).collect(),
+        }
+    }
+
+    /// The new ids for an old id, or `None` if the old id was not in the vocabulary.
+    pub fn get(&self, old: TokenId) -> Option<&[TokenId]> {
+        self.map.get(old.0).map(Vec::as_slice)
+    }
+
+    /// Translates a sequence encoded with the old vocabulary; it decodes to the same symbols.
+    pub fn translate(&self, ids: &[TokenId]) -> Result<Vec<TokenId>> {
+        let mut result = Vec::with_capacity(ids.len());
+        for &id in ids {
+            result.extend(self.get(id).ok_or(Error::UnknownTokenId(id))?);
+        }
+        Ok(result)
+    }
+}
+
+/// Rebuilds a token list (in id order) with only symbol tokens and the merges `keep` accepts.
+///
+/// A kept merge whose parts were removed is split into two other kept tokens with the same
+/// symbols if possible; otherwise its original parts are kept too. Those parts are then
+/// dropped again, longest first, wherever every merge using one can be re-split into two
+/// other remaining tokens. Merges spelling the same symbols as another kept token are folded
+/// into the one with the lower id. New ids follow the old order as far as merges referring
+/// to earlier ids allows.
+///
+/// Run tokens are never split; a removed run maps to copies of its symbol unless another kept
+/// token spells it.
+pub(crate) fn prune_tokens<S: Symbol>(
+    tokens: &[Token<S>],
+    keep: impl Fn(TokenId) -> bool,
+) -> (Vec<Token<S>>, TokenRemap) {
+    let mut symbols = Vec::<Vec<S>>::with_capacity(tokens.len());
+    for token in tokens {
+        symbols.push(match *token {
+            Token::Symbol(x) => vec![x],
+            Token::Merge(id0, id1) => [symbols[id0.0].as_slice(), &symbols[id1.0]].concat(),
+            Token::Run(id, len) => symbols[id.0].repeat(len),
+        });
+    }
+
+    // every kept token is represented by the lowest kept id with its symbols
+    let mut roots = (0..tokens.len())
+        .filter(|&old| match tokens[old] {
+            Token::Symbol(_) => true,
+            Token::Merge(..) | Token::Run(..) => keep(TokenId(old)),
+        })
+        .collect::<Vec<_>>();
+    let mut representatives = HashMap::<&[S], usize>::new();
+    for &old in &roots {
+        representatives.entry(&symbols[old]).or_insert(old);
+    }
+
+    // the parts of each kept merge, resolved shortest first so splits can use any shorter token
+    roots.sort_by_key(|&old| (symbols[old].len(), old));
+    let mut parts = HashMap::<usize, (usize, usize)>::new();
+    let mut resolved = HashMap::<&[S], usize>::new();
+
+    for root in roots {
+        let mut stack = vec![root];
+
+        while let Some(&old) = stack.last() {
+            let representative = *representatives.entry(&symbols[old]).or_insert(old);
+            if resolved.contains_key(symbols[old].as_slice()) {
+                stack.pop();
+                continue;
+            }
+            if representative != old {
+                stack.push(representative);
+                continue;
+            }
+
+            let split = match tokens[old] {
+                Token::Symbol(_) | Token::Run(..) => None,
+                Token::Merge(id0, id1) => {
+                    let b = &symbols[old];
+                    let original = (resolved.get(&symbols[id0.0][..]).copied())
+                        .zip(resolved.get(&symbols[id1.0][..]).copied());
+                    let other = || {
+                        (1..b.len())
+                            .find_map(|i| Some((*resolved.get(&b[..i])?, *resolved.get(&b[i..])?)))
+                    };
+                    match original.or_else(other) {
+                        Some(split) => Some(split),
+                        None => {
+                            stack.extend([id0.0, id1.0]);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            if let Some(split) = split {
+                parts.insert(old, split);
+            }
+            resolved.insert(&symbols[old], old);
+            stack.pop();
+        }
+    }
+
+    // drop the unused tokens kept only as parts, longest first, wherever every merge using
+    // them can be split into two other tokens instead
+    let mut users = HashMap::<usize, Vec<usize>>::new();
+    for (&old, &(left, right)) in &parts {
+        users.entry(left).or_default().push(old);
+        users.entry(right).or_default().push(old);
+    }
+    let mut unused = (resolved.values().copied())
+        .filter(|&old| !matches!(tokens[old], Token::Symbol(_)) && !keep(TokenId(old)))
+        .collect::<Vec<_>>();
+    unused.sort_by_key(|&old| (Reverse(symbols[old].len()), old));
+
+    for old in unused {
+        let mut splits = vec![];
+        for &user in users.get(&old).into_iter().flatten() {
+            let b = &symbols[user];
+            let split = (1..b.len()).find_map(|i| {
+                let split = (*resolved.get(&b[..i])?, *resolved.get(&b[i..])?);
+                (split.0 != old && split.1 != old).then_some(split)
+            });
+            match split {
+                Some(split) => splits.push((user, split)),
+                None => break,
+            }
+        }
+        if splits.len() < users.get(&old).map_or(0, Vec::len) {
+            continue;
+        }
+
+        resolved.remove(symbols[old].as_slice());
+        for part in parts
+            .remove(&old)
+            .into_iter()
+            .flat_map(|(left, right)| [left, right])
+        {
+            users.get_mut(&part).unwrap().retain(|&user| user != old);
+        }
+        users.remove(&old);
+        for (user, (left, right)) in splits {
+            parts.insert(user, (left, right));
+            users.entry(left).or_default().push(user);
+            users.entry(right).or_default().push(user);
+        }
+    }
+
+    // new ids in old order, each merge after its parts
+    let mut dependents = HashMap::<usize, Vec<usize>>::new();
+    let mut waiting = HashMap::<usize, usize>::new();
+    let mut ready = BinaryHeap::new();
+    for &old in resolved.values() {
+        match parts.get(&old) {
+            Some(&(left, right)) => {
+                dependents.entry(left).or_default().push(old);
+                if right != left {
+                    dependents.entry(right).or_default().push(old);
+                }
+                waiting.insert(old, if right != left { 2 } else { 1 });
+            }
+            // a run's symbol has a lower id, so it always gets its new id first
+            None => ready.push(Reverse(old)),
+        }
+    }
+
+    let mut new_tokens = vec![];
+    let mut new_ids = vec![None; tokens.len()];
+    while let Some(Reverse(old)) = ready.pop() {
+        new_ids[old] = Some(TokenId(new_tokens.len()));
+        new_tokens.push(match parts.get(&old) {
+            Some(&(left, right)) => Token::Merge(new_ids[left].unwrap(), new_ids[right].unwrap()),
+            None => match tokens[old] {
+                Token::Run(id, len) => Token::Run(new_ids[id.0].unwrap(), len),
+                token => token,
+            },
+        });
+
+        for &dependent in dependents.get(&old).into_iter().flatten() {
+            let count = waiting.get_mut(&dependent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push(Reverse(dependent));
+            }
+        }
+    }
+
+    let mut map = Vec::<Vec<TokenId>>::with_capacity(tokens.len());
+    for (old, token) in tokens.iter().enumerate() {
+        let ids = match resolved.get(symbols[old].as_slice()) {
+            Some(&representative) => vec![new_ids[representative].unwrap()],
+            None => match *token {
+                Token::Merge(id0, id1) => [map[id0.0].as_slice(), &map[id1.0]].concat(),
+                Token::Run(id, len) => map[id.0].repeat(len),
+                Token::Symbol(_) => unreachable!("symbol tokens are always kept"),
+            },
+        };
+        map.push(ids);
+    }
+
+    (new_tokens, TokenRemap { map })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vocab::Vocab;
+
+    fn bytes() -> Vec<Token> {
+        (0..=u8::MAX).map(Token::Symbol).collect()
+    }
+
+    #[test]
+    fn test_prune_tokens() {
+        let (a, b, c) = (
+            TokenId(b'a' as usize),
+            TokenId(b'b' as usize),
+            TokenId(b'c' as usize),
+        );
+        let mut tokens = bytes();
+        tokens.extend([
+            Token::Merge(a, b),            // 256: ab
+            Token::Merge(TokenId(256), c), // 257: abc
+            Token::Merge(b, c),            // 258: bc
+            Token::Merge(TokenId(257), a), // 259: abca
+            Token::Merge(a, TokenId(258)), // 260: abc again
+        ]);
+
+        // ab is only a stepping stone: abc can be split into (a, bc) instead
+        let (new_tokens, remap) = prune_tokens(&tokens, |id| id.0 != 256);
+
+        let mut expected = bytes();
+        expected.extend([
+            Token::Merge(b, c),            // 256: bc
+            Token::Merge(a, TokenId(256)), // 257: abc
+            Token::Merge(TokenId(257), a), // 258: abca
+        ]);
+        assert_eq!(new_tokens, expected);
+        assert_eq!(remap.get(TokenId(256)), Some(&[a, b][..]));
+        assert_eq!(remap.get(TokenId(257)), Some(&[TokenId(257)][..]));
+        assert_eq!(remap.get(TokenId(258)), Some(&[TokenId(256)][..]));
+        assert_eq!(remap.get(TokenId(260)), Some(&[TokenId(257)][..]));
+        assert_eq!(remap.get(TokenId(261)), None);
+
+        // without bc, abc still needs ab
+        let (new_tokens, remap) = prune_tokens(&tokens, |id| id.0 == 257);
+        assert_eq!(new_tokens, tokens[..258]);
+        assert_eq!(remap.get(TokenId(258)), Some(&[b, c][..]));
+        assert_eq!(remap.get(TokenId(259)), Some(&[TokenId(257), a][..]));
+
+        assert_eq!(
+            remap.translate(&[TokenId(259), TokenId(258), c]).unwrap(),
+            vec![TokenId(257), a, b, c, c]
+        );
+        assert!(matches!(
+            remap.translate(&[TokenId(261)]),
+            Err(Error::UnknownTokenId(TokenId(261)))
+        ));
+    }
+
+    #[test]
+    fn test_prune_runs() {
+        let zero = TokenId(0);
+        let mut tokens = bytes();
+        tokens.extend([
+            Token::Run(zero, 10),                               // 256: 10 zeros
+            Token::Merge(zero, zero),                           // 257: 2 zeros
+            Token::Merge(TokenId(256), TokenId(257)),           // 258: 12 zeros
+            Token::Merge(TokenId(257), TokenId(b'x' as usize)), // 259: 00x
+            Token::Run(zero, 12),                               // 260: 12 zeros again
+        ]);
+
+        let decode = |tokens: &[Token], ids: &[TokenId]| {
+            let mut vocab = Vocab::new();
+            for &token in tokens {
+                vocab.push(token);
+            }
+            (ids.iter())
+                .flat_map(|&id| vocab.expansion(id).unwrap().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        for keep in [
+            |id: TokenId| id.0 == 258,
+            |id: TokenId| id.0 == 259,
+            |id: TokenId| id.0 == 260,
+            |id: TokenId| id.0 >= 258,
+            |_: TokenId| false,
+        ] {
+            let (new_tokens, remap) = prune_tokens(&tokens, keep);
+            for old in 0..tokens.len() {
+                let ids = remap.get(TokenId(old)).unwrap();
+                assert_eq!(decode(&new_tokens, ids), decode(&tokens, &[TokenId(old)]));
+            }
+        }
+
+        // the run the kept merge is made of is kept with it
+        let (new_tokens, remap) = prune_tokens(&tokens, |id| id.0 == 258);
+        assert_eq!(new_tokens, tokens[..259]);
+        assert_eq!(remap.get(TokenId(260)), Some(&[TokenId(258)][..]));
+    }
+}
@@ -1,5 +1,35 @@
+use crate::error::{Error, Result};
+use crate::symbol::Symbol;
 use crate::token::{self, Token, TokenId};
 use indexmap::{IndexMap, IndexSet};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::Range;
+
+/// A token and the range of symbols it covers in its input
+pub type TokenSpan = (TokenId, Range<usize>);
+
+/// Finds tokens by id: the map from [`crate::bpe::Bpe::ids_to_tokens`], or a `Bpe`'s own storage
+pub trait IdsToTokens<S> {
+    fn token(&self, id: TokenId) -> Option<Token<S>>;
+}
+
+impl<S: Symbol> IdsToTokens<S> for IndexMap<TokenId, Token<S>> {
+    fn token(&self, id: TokenId) -> Option<Token<S>> {
+        self.get(&id).copied()
+    }
+}
+
+/// Finds ids by token: the map from [`crate::bpe::Bpe::tokens_to_ids`], or a `Bpe`'s own storage
+pub trait TokensToIds<S> {
+    fn id(&self, token: &Token<S>) -> Option<TokenId>;
+}
+
+impl<S: Symbol> TokensToIds<S> for IndexMap<Token<S>, TokenId> {
+    fn id(&self, token: &Token<S>) -> Option<TokenId> {
+        self.get(token).copied()
+    }
+}
 
 pub fn condense(
     pattern: Vec<TokenId>,
@@ -18,6 +48,56 @@ pub fn condense(
     result
 }
 
+/// Merges pairs in order of their merged id (lowest first), and left to right within an id.
+///
+/// Since a `Bpe` assigns ids in training order, this applies merges in the same order as training.
+pub fn condense_ranked(
+    pattern: Vec<TokenId>,
+    merge_if: impl Fn(TokenId, TokenId) -> Option<TokenId>,
+) -> Vec<TokenId> {
+    let len = pattern.len();
+    let mut ids = pattern.into_iter().map(Some).collect::<Vec<_>>();
+    // linked list over the original indices; `len` and `usize::MAX` mark the ends
+    let mut prev = (0..len).map(|i| i.wrapping_sub(1)).collect::<Vec<_>>();
+    let mut next = (1..=len).collect::<Vec<_>>();
+
+    let pair_at = |ids: &[Option<TokenId>], next: &[usize], i: usize| -> Option<TokenId> {
+        let id0 = ids.get(i).copied().flatten()?;
+        let id1 = ids.get(*next.get(i)?).copied().flatten()?;
+        merge_if(id0, id1)
+    };
+
+    let mut queue = BinaryHeap::new();
+    for i in 0..len {
+        if let Some(merged) = pair_at(&ids, &next, i) {
+            queue.push(Reverse((merged, i)));
+        }
+    }
+
+    while let Some(Reverse((merged, i))) = queue.pop() {
+        if pair_at(&ids, &next, i) != Some(merged) {
+            // stale: a neighbour was merged since this entry was queued
+            continue;
+        }
+
+        let j = next[i];
+        ids[i] = Some(merged);
+        ids[j] = None;
+        next[i] = next[j];
+        if let Some(n) = prev.get_mut(next[i]) {
+            *n = i;
+        }
+
+        for k in [prev[i], i] {
+            if let Some(merged) = pair_at(&ids, &next, k) {
+                queue.push(Reverse((merged, k)));
+            }
+        }
+    }
+
+    ids.into_iter().flatten().collect()
+}
+
 pub fn condense_and_collect(
     pattern: Vec<TokenId>,
     merge_if: impl Fn(TokenId, TokenId) -> Option<TokenId>,
@@ -37,20 +117,31 @@ pub fn condense_and_collect(
     meta_result
 }
 
-pub fn expand(pattern: Vec<TokenId>, ids_to_tokens: &IndexMap<TokenId, Token>) -> Vec<TokenId> {
+pub fn expand<S: Symbol>(
+    pattern: Vec<TokenId>,
+    ids_to_tokens: &impl IdsToTokens<S>,
+) -> Vec<TokenId> {
+    try_expand(pattern, ids_to_tokens).unwrap_or_else(|e| panic!("{e}"))
+}
+
+pub fn try_expand<S: Symbol>(
+    pattern: Vec<TokenId>,
+    ids_to_tokens: &impl IdsToTokens<S>,
+) -> Result<Vec<TokenId>> {
     let mut result = pattern;
 
     loop {
         let mut unmerged = vec![];
 
         for id in result.iter() {
-            match ids_to_tokens.get(id) {
+            match ids_to_tokens.token(*id) {
                 Some(Token::Merge(id0, id1)) => {
-                    unmerged.push(*id0);
-                    unmerged.push(*id1);
+                    unmerged.push(id0);
+                    unmerged.push(id1);
                 }
-                Some(Token::Byte(_)) => unmerged.push(*id),
-                None => panic!("TokenId not in encoded set"),
+                Some(Token::Run(id, len)) => unmerged.resize(unmerged.len() + len, id),
+                Some(Token::Symbol(_)) => unmerged.push(*id),
+                None => return Err(Error::UnknownTokenId(*id)),
             }
         }
 
@@ -60,47 +151,176 @@ pub fn expand(pattern: Vec<TokenId>, ids_to_tokens: &IndexMap<TokenId, Token>) -
         result = unmerged;
     }
 
-    result
+    Ok(result)
+}
+
+/// Pairs each token of `pattern` with its symbol offsets, the pattern starting at 0.
+pub fn spans<S: Symbol>(
+    pattern: &[TokenId],
+    ids_to_tokens: &impl IdsToTokens<S>,
+) -> Vec<TokenSpan> {
+    try_spans(pattern, ids_to_tokens).unwrap_or_else(|e| panic!("{e}"))
+}
+
+pub fn try_spans<S: Symbol>(
+    pattern: &[TokenId],
+    ids_to_tokens: &impl IdsToTokens<S>,
+) -> Result<Vec<TokenSpan>> {
+    let mut lens = HashMap::new();
+    let mut offset = 0;
+    pattern
+        .iter()
+        .map(|&id| {
+            let len = token_len(id, ids_to_tokens, &mut lens)?;
+            offset += len;
+            Ok((id, offset - len..offset))
+        })
+        .collect()
+}
+
+/// The number of symbols `id` expands to, remembering the lengths of its parts in `lens`.
+fn token_len<S: Symbol>(
+    id: TokenId,
+    ids_to_tokens: &impl IdsToTokens<S>,
+    lens: &mut HashMap<TokenId, usize>,
+) -> Result<usize> {
+    if let Some(&len) = lens.get(&id) {
+        return Ok(len);
+    }
+    let len = match ids_to_tokens.token(id) {
+        Some(Token::Symbol(_)) => 1,
+        Some(Token::Merge(id0, id1)) => {
+            token_len(id0, ids_to_tokens, lens)? + token_len(id1, ids_to_tokens, lens)?
+        }
+        Some(Token::Run(id, len)) => token_len(id, ids_to_tokens, lens)? * len,
+        None => return Err(Error::UnknownTokenId(id)),
+    };
+    lens.insert(id, len);
+    Ok(len)
 }
 
-pub fn range(
+/// As [`expand`], keeping offsets: each symbol token gets its own position within its
+/// token's span.
+pub fn expand_with_spans<S: Symbol>(
+    spans: Vec<TokenSpan>,
+    ids_to_tokens: &impl IdsToTokens<S>,
+) -> Vec<TokenSpan> {
+    try_expand_with_spans(spans, ids_to_tokens).unwrap_or_else(|e| panic!("{e}"))
+}
+
+pub fn try_expand_with_spans<S: Symbol>(
+    spans: Vec<TokenSpan>,
+    ids_to_tokens: &impl IdsToTokens<S>,
+) -> Result<Vec<TokenSpan>> {
+    let mut result = vec![];
+    for (id, range) in spans {
+        let expanded = try_expand(vec![id], ids_to_tokens)?;
+        let start = range.start;
+        result.extend(
+            (expanded.into_iter().enumerate()).map(|(i, id)| (id, start + i..start + i + 1)),
+        );
+    }
+    Ok(result)
+}
+
+pub fn range<S: Symbol>(
     pattern: Vec<TokenId>,
-    ids_to_tokens: &IndexMap<TokenId, Token>,
-    tokens_to_ids: &IndexMap<Token, TokenId>,
+    ids_to_tokens: &impl IdsToTokens<S>,
+    tokens_to_ids: &impl TokensToIds<S>,
 ) -> IndexSet<TokenId> {
-    let mut result = IndexSet::new();
+    try_range(pattern, ids_to_tokens, tokens_to_ids).unwrap_or_else(|e| panic!("{e}"))
+}
 
-    let expanded = expand(pattern.clone(), ids_to_tokens);
-    result.extend(expanded.clone());
+pub fn try_range<S: Symbol>(
+    pattern: Vec<TokenId>,
+    ids_to_tokens: &impl IdsToTokens<S>,
+    tokens_to_ids: &impl TokensToIds<S>,
+) -> Result<IndexSet<TokenId>> {
+    let mut result = IndexSet::new();
+    result.extend(pattern.iter().copied());
+
+    let expanded = try_expand(pattern, ids_to_tokens)?;
+    result.extend(expanded.iter().copied());
+    let mut start = 0;
+    while start < expanded.len() {
+        let len = (expanded[start..].iter())
+            .take_while(|&&id| id == expanded[start])
+            .count();
+        result.extend(run_ids(expanded[start], len, tokens_to_ids));
+        start += len;
+    }
     result.extend(condense_and_collect(expanded, |id0, id1| {
-        tokens_to_ids.get(&Token::Merge(id0, id1)).copied()
+        tokens_to_ids.id(&Token::Merge(id0, id1))
     }));
-    result
+    Ok(result)
 }
 
-pub fn to_ids(data: &[u8], tokens_to_ids: &IndexMap<Token, TokenId>) -> Vec<TokenId> {
-    let result: Vec<TokenId> = data
-        .iter()
+/// The run tokens for `len` copies of the symbol token `symbol`: the longest one, then the
+/// longest for what is left, as in [`crate::bpe::Bpe::encode`].
+fn run_ids<S: Symbol>(
+    symbol: TokenId,
+    mut len: usize,
+    tokens_to_ids: &impl TokensToIds<S>,
+) -> Vec<TokenId> {
+    let mut ids = vec![];
+    let mut run_len = len;
+    while run_len >= 2 {
+        match tokens_to_ids.id(&Token::Run(symbol, run_len)) {
+            Some(id) => {
+                ids.push(id);
+                len %= run_len;
+                run_len = len;
+            }
+            None => run_len -= 1,
+        }
+    }
+    ids
+}
+
+pub fn to_ids<S: Symbol>(data: &[S], tokens_to_ids: &impl TokensToIds<S>) -> Vec<TokenId> {
+    try_to_ids(data, tokens_to_ids).unwrap_or_else(|e| panic!("{e}"))
+}
+
+pub fn try_to_ids<S: Symbol>(
+    data: &[S],
+    tokens_to_ids: &impl TokensToIds<S>,
+) -> Result<Vec<TokenId>> {
+    data.iter()
         .map(|&x| {
-            *tokens_to_ids
-                .get(&Token::Byte(x))
-                .expect("all byte values should be encoded")
+            tokens_to_ids
+                .id(&Token::Symbol(x))
+                .ok_or(Error::MissingSymbol(x.to_u64()))
         })
-        .collect();
-    result
+        .collect()
 }
 
-pub fn to_bytes(data: &[TokenId], ids_to_tokens: &IndexMap<TokenId, Token>) -> Vec<u8> {
-    let result = data;
-    result
-        .iter()
-        .map(|id| match ids_to_tokens.get(id) {
-            Some(Token::Byte(b)) => *b,
-            _ => panic!("internal decoding error"),
+pub fn to_symbols<S: Symbol>(data: &[TokenId], ids_to_tokens: &impl IdsToTokens<S>) -> Vec<S> {
+    try_to_symbols(data, ids_to_tokens).unwrap_or_else(|e| panic!("{e}"))
+}
+
+pub fn try_to_symbols<S: Symbol>(
+    data: &[TokenId],
+    ids_to_tokens: &impl IdsToTokens<S>,
+) -> Result<Vec<S>> {
+    data.iter()
+        .map(|id| match ids_to_tokens.token(*id) {
+            Some(Token::Symbol(b)) => Ok(b),
+            Some(Token::Merge(..) | Token::Run(..)) => Err(Error::NotASymbol(*id)),
+            None => Err(Error::UnknownTokenId(*id)),
         })
         .collect()
 }
 
+#[deprecated(note = "use `to_symbols`")]
+pub fn to_bytes(data: &[TokenId], ids_to_tokens: &impl IdsToTokens<u8>) -> Vec<u8> {
+    to_symbols(data, ids_to_tokens)
+}
+
+#[deprecated(note = "use `try_to_symbols`")]
+pub fn try_to_bytes(data: &[TokenId], ids_to_tokens: &impl IdsToTokens<u8>) -> Result<Vec<u8>> {
+    try_to_symbols(data, ids_to_tokens)
+}
+
 #[cfg(test)]
 mod tests {
     use rand::RngCore;
@@ -114,18 +334,18 @@ mod tests {
         let bpe = Bpe::new(&[&[1, 2, 1, 2, 3, 4, 5, 6]]);
         let range = range(
             bpe.encode(&[1, 2, 3, 4, 5, 6]),
-            &bpe.ids_to_tokens(),
-            &bpe.tokens_to_ids(),
+            bpe.ids_to_tokens(),
+            bpe.tokens_to_ids(),
         );
         println!("{:?}", range);
     }
 
     fn f(pattern1: Vec<TokenId>, pattern2: Vec<TokenId>, bpe: &Bpe) -> Vec<TokenId> {
-        let range = range(pattern2.clone(), &bpe.ids_to_tokens(), &bpe.tokens_to_ids());
+        let range = range(pattern2.clone(), bpe.ids_to_tokens(), bpe.tokens_to_ids());
         println!("{:?}", range);
 
         let range_vec = range.iter().copied().collect::<Vec<_>>();
-        print_tokens(range_vec, bpe, |id| false);
+        print_tokens(range_vec, bpe, |_id| false);
 
         let merge_if = |id0: TokenId, id1: TokenId| -> Option<TokenId> {
             bpe.tokens_to_ids()
@@ -136,15 +356,15 @@ mod tests {
 
         //            let merge_if = |id0: &TokenId, id1: &TokenId| -> bool { true };
 
-        let e = expand(pattern1.clone(), &bpe.ids_to_tokens());
+        let e = expand(pattern1.clone(), bpe.ids_to_tokens());
         println!("{:?}", e);
 
         let c = condense(e.clone(), merge_if);
         println!("{:?}", c);
 
-        print_tokens(pattern1.clone(), &bpe, |id| !range.contains(id));
-        print_tokens(e, &bpe, |id| !range.contains(id));
-        print_tokens(c.clone(), &bpe, |id| !range.contains(id));
+        print_tokens(pattern1.clone(), bpe, |id| !range.contains(id));
+        print_tokens(e, bpe, |id| !range.contains(id));
+        print_tokens(c.clone(), bpe, |id| !range.contains(id));
 
         c
     }
@@ -155,9 +375,9 @@ mod tests {
         rand::thread_rng().fill_bytes(&mut pattern1);
         let pattern1 = &pattern1;
 
-        let mut pattern2 = pattern1.clone();
+        let mut pattern2 = *pattern1;
         rand::thread_rng().fill_bytes(&mut pattern2[8..16]);
-        let pattern2 = &pattern2;
+        let _pattern2 = &pattern2;
 
         let pattern1 = &[
             235, 4, 39, 149, 209, 252, 162, 130, 117, 122, 38, 174, 226, 121, 100, 248, 135, 230,
@@ -186,4 +406,68 @@ mod tests {
             serde_json::from_str::<Vec<TokenId>>("[269,254,154,20,59,241,62,200,155,262]").unwrap();
         assert_eq!(c2, expected);
     }
+
+    #[test]
+    fn test_spans() {
+        let data = [1, 2, 1, 2, 3, 3, 3, 3, 3, 3].repeat(2);
+        let config = crate::config::BpeConfig::new().min_run_len(4);
+        let bpe = Bpe::with_config(&[&data], &config);
+        let ids_to_tokens = bpe.ids_to_tokens();
+
+        let spans = spans(&bpe.encode(&data), ids_to_tokens);
+        assert_eq!(spans.last().unwrap().1.end, data.len());
+        assert!(spans.windows(2).all(|w| w[0].1.end == w[1].1.start));
+        for (id, range) in &spans {
+            assert_eq!(bpe.decode(&[*id]), &data[range.clone()]);
+        }
+        assert!(spans.iter().any(|(_, range)| range.len() == 6));
+
+        let expanded = expand_with_spans(spans, ids_to_tokens);
+        assert_eq!(expanded.len(), data.len());
+        for (i, (id, range)) in expanded.into_iter().enumerate() {
+            assert_eq!(range, i..i + 1);
+            assert_eq!(id, TokenId(data[i] as usize));
+        }
+
+        let merged = bpe.tokens_to_ids()[&Token::Merge(TokenId(1), TokenId(2))];
+        let shifted = expand_with_spans(vec![(merged, 10..12)], ids_to_tokens);
+        assert_eq!(shifted, vec![(TokenId(1), 10..11), (TokenId(2), 11..12)]);
+        assert!(matches!(
+            try_spans(&[TokenId(999)], ids_to_tokens),
+            Err(Error::UnknownTokenId(TokenId(999)))
+        ));
+    }
+
+    #[test]
+    fn test_fallible() {
+        let bpe = Bpe::new(&[&[1, 2, 1, 2]]);
+        let ids_to_tokens = bpe.ids_to_tokens();
+
+        assert_eq!(
+            try_expand(vec![TokenId(256), TokenId(3)], ids_to_tokens).unwrap(),
+            vec![TokenId(1), TokenId(2), TokenId(3)]
+        );
+        assert!(matches!(
+            try_expand(vec![TokenId(3), TokenId(999)], ids_to_tokens),
+            Err(Error::UnknownTokenId(TokenId(999)))
+        ));
+
+        assert_eq!(
+            try_to_symbols(&[TokenId(1), TokenId(2)], ids_to_tokens).unwrap(),
+            vec![1, 2]
+        );
+        assert!(matches!(
+            try_to_symbols(&[TokenId(256)], ids_to_tokens),
+            Err(Error::NotASymbol(TokenId(256)))
+        ));
+        assert!(matches!(
+            try_to_symbols(&[TokenId(999)], ids_to_tokens),
+            Err(Error::UnknownTokenId(TokenId(999)))
+        ));
+
+        assert!(matches!(
+            try_to_ids(&[7u8], &IndexMap::new()),
+            Err(Error::MissingSymbol(7))
+        ));
+    }
 }
@@ -0,0 +1,114 @@
+//! One input re-tokenized relative to another (see [`crate::bpe::Bpe::retokenize_relative_to`])
+
+use crate::token::TokenId;
+use std::ops::Range;
+
+/// Whether a reference input has a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    /// In or reachable from the reference (see [`crate::token_set::TokenSet::reachable`]).
+    Shared,
+    /// Not in the reference.
+    Novel,
+}
+
+/// Consecutive tokens with the same [`Relation`] to the reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelativeSpan {
+    pub relation: Relation,
+    pub ids: Vec<TokenId>,
+    /// Symbol offsets of the tokens in the input.
+    pub range: Range<usize>,
+}
+
+impl RelativeSpan {
+    pub fn is_shared(&self) -> bool {
+        self.relation == Relation::Shared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpe::Bpe;
+    use crate::config::BpeConfig;
+    use crate::error::Error;
+    use crate::token_set::TokenSet;
+
+    #[test]
+    fn test_retokenize_relative_to() {
+        let file1 = b"Hello 12345 World";
+        let file2 = b"Hello World";
+        let bpe = Bpe::new(&[file1, file2]);
+        let (pattern1, pattern2) = (bpe.encode(file1), bpe.encode(file2));
+
+        let spans = bpe.retokenize_relative_to(&pattern1, &pattern2);
+        let text = |span: &RelativeSpan| String::from_utf8(bpe.decode(&span.ids)).unwrap();
+        let tagged = spans
+            .iter()
+            .map(|span| (span.relation, text(span)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tagged,
+            vec![
+                (Relation::Shared, "Hello ".to_string()),
+                (Relation::Novel, "12345".to_string()),
+                (Relation::Shared, " World".to_string()),
+            ]
+        );
+
+        // spans tile the input, alternate, and their ids decode to the bytes at their offsets
+        assert_eq!(spans.first().unwrap().range.start, 0);
+        assert_eq!(spans.last().unwrap().range.end, file1.len());
+        assert!(spans.windows(2).all(|w| w[0].range.end == w[1].range.start));
+        assert!(spans.windows(2).all(|w| w[0].relation != w[1].relation));
+        for span in &spans {
+            assert_eq!(bpe.decode(&span.ids), &file1[span.range.clone()]);
+        }
+
+        // relative to itself, an input is all shared
+        let spans = bpe.retokenize_relative_to(&pattern2, &pattern2);
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].is_shared());
+        assert_eq!(spans[0].range, 0..file2.len());
+
+        let nothing = TokenSet::new();
+        let spans = bpe.retokenize_within(&pattern1, &nothing);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].relation, Relation::Novel);
+        assert!(bpe.retokenize_relative_to(&[], &pattern2).is_empty());
+
+        assert!(matches!(
+            bpe.try_retokenize_relative_to(&pattern1, &[TokenId(999)]),
+            Err(Error::UnknownTokenId(TokenId(999)))
+        ));
+    }
+
+    #[test]
+    fn test_retokenize_runs() {
+        let data = [b"ab".as_slice(), &[0; 10], b"ab", &[0; 10]].concat();
+        let bpe = Bpe::with_config(&[&data], &BpeConfig::new().min_run_len(4));
+        let pattern = bpe.encode(&data);
+
+        // runs keep their run tokens, so re-tokenizing against itself changes nothing
+        let spans = bpe.retokenize_relative_to(&pattern, &pattern);
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].is_shared());
+        assert_eq!(spans[0].ids, pattern);
+
+        // a novel run is one novel token
+        let run = bpe.encode(&[0; 10]);
+        assert_eq!(run.len(), 1);
+        let spans = bpe.retokenize_relative_to(&pattern, &bpe.encode(b"ab"));
+        assert_eq!(spans.len(), 4);
+        assert_eq!((spans[1].relation, &spans[1].ids), (Relation::Novel, &run));
+
+        // where the reference has the symbol but not the run token, the run is shared symbols
+        let other = [b"ab".as_slice(), &[0; 3]].concat();
+        let spans = bpe.retokenize_relative_to(&pattern, &bpe.encode(&other));
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].is_shared());
+        assert!(!spans[0].ids.contains(&run[0]));
+        assert_eq!(bpe.decode(&spans[0].ids), data);
+    }
+}
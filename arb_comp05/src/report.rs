@@ -0,0 +1,113 @@
+//! Summary statistics for a trained [`crate::bpe::Bpe`]
+
+use crate::token::TokenId;
+use std::fmt;
+
+/// Number of tokens listed in [`BpeReport::longest_tokens`]
+pub const LONGEST_TOKENS: usize = 10;
+
+/// Longest tokens are shown with at most this many bytes.
+const SHOWN_BYTES: usize = 32;
+
+/// A vocabulary summary, from [`crate::bpe::Bpe::report`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BpeReport {
+    pub vocab_size: usize,
+    pub merges: usize,
+    pub max_merge_depth: usize,
+    /// The longest tokens and their bytes, longest first (ties by id).
+    pub longest_tokens: Vec<(TokenId, Vec<u8>)>,
+    /// One entry per training input; empty for a loaded vocabulary.
+    pub inputs: Vec<InputReport>,
+}
+
+/// How one training input was segmented
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputReport {
+    pub bytes: usize,
+    pub tokens: usize,
+}
+
+impl InputReport {
+    /// Input bytes per token (1.0 for an empty input).
+    pub fn compression_ratio(&self) -> f64 {
+        if self.tokens == 0 {
+            1.0
+        } else {
+            self.bytes as f64 / self.tokens as f64
+        }
+    }
+}
+
+impl fmt::Display for BpeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "vocabulary: {} tokens ({} merges), max merge depth {}",
+            self.vocab_size, self.merges, self.max_merge_depth
+        )?;
+
+        writeln!(f, "longest tokens:")?;
+        for (id, bytes) in &self.longest_tokens {
+            let shown = bytes[..bytes.len().min(SHOWN_BYTES)].escape_ascii();
+            let more = if bytes.len() > SHOWN_BYTES { "..." } else { "" };
+            writeln!(
+                f,
+                "  {:>6}  {:>5} bytes  \"{shown}\"{more}",
+                id.0,
+                bytes.len()
+            )?;
+        }
+
+        if self.inputs.is_empty() {
+            return writeln!(f, "training inputs: none recorded");
+        }
+        writeln!(f, "training inputs:")?;
+        for (i, input) in self.inputs.iter().enumerate() {
+            writeln!(
+                f,
+                "  {i:>3}: {} bytes -> {} tokens ({:.2}x)",
+                input.bytes,
+                input.tokens,
+                input.compression_ratio()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let report = BpeReport {
+            vocab_size: 257,
+            merges: 1,
+            max_merge_depth: 1,
+            longest_tokens: vec![(TokenId(256), b"a\n".to_vec()), (TokenId(0), vec![0])],
+            inputs: vec![
+                InputReport {
+                    bytes: 5,
+                    tokens: 2,
+                },
+                InputReport {
+                    bytes: 0,
+                    tokens: 0,
+                },
+            ],
+        };
+
+        let expected = [
+            "vocabulary: 257 tokens (1 merges), max merge depth 1",
+            "longest tokens:",
+            "     256      2 bytes  \"a\\n\"",
+            "       0      1 bytes  \"\\x00\"",
+            "training inputs:",
+            "    0: 5 bytes -> 2 tokens (2.50x)",
+            "    1: 0 bytes -> 0 tokens (1.00x)",
+        ];
+        assert_eq!(report.to_string().lines().collect::<Vec<_>>(), expected);
+    }
+}
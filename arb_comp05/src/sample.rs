@@ -0,0 +1,163 @@
+//! Training on a seeded sample of windows from large inputs (see [`crate::bpe::Bpe::from_samples`])
+
+use std::ops::Range;
+
+/// Estimated peak training memory per sampled symbol: the pair index keeps each symbol
+/// in a linked list and each pair occurrence in an ordered set.
+pub const BYTES_PER_SYMBOL: usize = 128;
+
+/// How [`crate::bpe::Bpe::from_samples`] samples its inputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sampling {
+    /// Length in symbols of each sampled window.
+    pub window_len: usize,
+    /// Approximate peak memory in bytes for training, which bounds the total sample size.
+    pub memory_budget: usize,
+    /// Seeds the choice of windows: the same seed and input lengths always give the same sample.
+    pub seed: u64,
+}
+
+impl Default for Sampling {
+    /// 4 KiB windows within a 256 MiB budget (about 2 MiB of samples).
+    fn default() -> Self {
+        Self {
+            window_len: 4096,
+            memory_budget: 256 << 20,
+            seed: 0,
+        }
+    }
+}
+
+impl Sampling {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn window_len(mut self, len: usize) -> Self {
+        self.window_len = len.max(1);
+        self
+    }
+
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The most symbols sampled from all inputs together.
+    pub fn max_symbols(&self) -> usize {
+        (self.memory_budget / BYTES_PER_SYMBOL).max(1)
+    }
+
+    /// Chooses the windows to train on in inputs of lengths `lens`, sorted and merged
+    /// where adjacent, per input.
+    ///
+    /// Inputs that fit an even share of [`Sampling::max_symbols`] are taken whole, and what
+    /// they leave is shared evenly by the longer ones. Those contribute windows at random
+    /// multiples of `window_len`: the ones that rank first by a fixed hash of the seed,
+    /// the input's index and the window's position.
+    pub fn windows(&self, lens: &[usize]) -> Vec<Vec<Range<usize>>> {
+        let mut order = (0..lens.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| lens[i]);
+
+        let mut shares = vec![0; lens.len()];
+        let mut left = self.max_symbols();
+        for (k, &i) in order.iter().enumerate() {
+            shares[i] = lens[i].min(left / (lens.len() - k));
+            left -= shares[i];
+        }
+
+        (lens.iter().zip(shares).enumerate())
+            .map(|(i, (&len, share))| {
+                if share == len {
+                    return if len == 0 {
+                        vec![]
+                    } else {
+                        std::iter::once(0..len).collect()
+                    };
+                }
+
+                let window_len = self.window_len.max(1).min(share.max(1));
+                let count = share / window_len;
+                let input_seed = mix(mix(self.seed) ^ i as u64);
+                let mut chosen = (0..len / window_len).collect::<Vec<_>>();
+                if count < chosen.len() {
+                    chosen.select_nth_unstable_by_key(count, |&slot| mix(input_seed ^ slot as u64));
+                    chosen.truncate(count);
+                }
+                chosen.sort_unstable();
+
+                let mut windows: Vec<Range<usize>> = vec![];
+                for slot in chosen {
+                    let start = slot * window_len;
+                    match windows.last_mut() {
+                        Some(last) if last.end == start => last.end += window_len,
+                        _ => windows.push(start..start + window_len),
+                    }
+                }
+                windows
+            })
+            .collect()
+    }
+}
+
+/// The splitmix64 finalizer: samples depend only on it, not on a random number generator
+/// that may change between versions of a crate.
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Progress reported by [`crate::bpe::Bpe::from_samples`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrainProgress {
+    /// The sample is chosen: this many windows with this many symbols in total.
+    Sampled { windows: usize, symbols: usize },
+    /// Another merge was learned, making this many.
+    Merged { merges: usize },
+    /// This many of the full inputs have been encoded to count token usage.
+    Encoded { inputs: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        let sampling = Sampling::new()
+            .window_len(10)
+            .memory_budget(100 * BYTES_PER_SYMBOL);
+        assert_eq!(sampling.max_symbols(), 100);
+
+        // the short inputs fit their share, the long ones share what is left
+        let lens = [1000, 5, 0, 2000];
+        let windows = sampling.windows(&lens);
+        assert_eq!(windows[1], vec![0..5]);
+        assert!(windows[2].is_empty());
+        for i in [0, 3] {
+            let len = |windows: &[Range<usize>]| windows.iter().map(|w| w.len()).sum::<usize>();
+            assert_eq!(len(&windows[i]), 40);
+            assert!(windows[i].windows(2).all(|w| w[0].end < w[1].start));
+            assert!(windows[i]
+                .iter()
+                .all(|w| w.start % 10 == 0 && w.end <= lens[i]));
+        }
+
+        assert_eq!(sampling.windows(&lens), windows);
+        assert_ne!(sampling.clone().seed(1).windows(&lens), windows);
+        assert_eq!(sampling.windows(&[50, 50]), vec![vec![0..50], vec![0..50]]);
+
+        // the same on every platform and with any version of the dependencies
+        let fixed = Sampling::new()
+            .window_len(10)
+            .memory_budget(30 * BYTES_PER_SYMBOL);
+        assert_eq!(fixed.windows(&[100]), vec![vec![0..20, 90..100]]);
+    }
+}
@@ -0,0 +1,120 @@
+//! The base alphabet a [`crate::bpe::Bpe`] merges: bytes by default, or wider units
+//!
+//! BPE itself only sees `TokenId`s, so any small `Copy` value works as a symbol:
+//! `u16`/`u32` words for aligned binary data, `char`s for text, or `u32` ids
+//! of interned lines (see [`intern`]) for logs.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+pub trait Symbol:
+    Copy + Ord + Hash + Debug + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+    /// Identifies the symbol type in saved vocabularies.
+    const NAME: &'static str;
+
+    fn to_u64(self) -> u64;
+
+    /// The symbol with this value, or `None` if it is out of range.
+    fn from_u64(x: u64) -> Option<Self>;
+
+    /// Symbols every vocabulary starts with, in id order.
+    ///
+    /// Other symbols get ids as training meets them. Bytes are all present up front,
+    /// so any byte input can be encoded.
+    fn alphabet() -> Vec<Self> {
+        vec![]
+    }
+}
+
+impl Symbol for u8 {
+    const NAME: &'static str = "u8";
+
+    fn to_u64(self) -> u64 {
+        self.into()
+    }
+
+    fn from_u64(x: u64) -> Option<Self> {
+        x.try_into().ok()
+    }
+
+    fn alphabet() -> Vec<Self> {
+        (0..=u8::MAX).collect()
+    }
+}
+
+macro_rules! impl_symbol {
+    ($($t:ty),*) => {$(
+        impl Symbol for $t {
+            const NAME: &'static str = stringify!($t);
+
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn from_u64(x: u64) -> Option<Self> {
+                x.try_into().ok()
+            }
+        }
+    )*};
+}
+
+impl_symbol!(u16, u32, u64);
+
+impl Symbol for char {
+    const NAME: &'static str = "char";
+
+    fn to_u64(self) -> u64 {
+        u32::from(self).into()
+    }
+
+    fn from_u64(x: u64) -> Option<Self> {
+        char::from_u32(x.try_into().ok()?)
+    }
+}
+
+/// Replaces each item with a `u32` id, numbered by first occurrence,
+/// returning the ids and the distinct items.
+///
+/// Interning the lines of a log lets a `Bpe<u32>` learn recurring runs of whole lines.
+pub fn intern<T: Hash + Eq + Clone>(items: impl IntoIterator<Item = T>) -> (Vec<u32>, Vec<T>) {
+    let mut ids = HashMap::new();
+    let mut distinct = vec![];
+
+    let symbols = items
+        .into_iter()
+        .map(|item| {
+            *ids.entry(item.clone()).or_insert_with(|| {
+                distinct.push(item);
+                distinct.len() as u32 - 1
+            })
+        })
+        .collect();
+
+    (symbols, distinct)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(u8::alphabet().len(), 256);
+        assert_eq!(u8::from_u64(255), Some(255));
+        assert_eq!(u8::from_u64(256), None);
+        assert_eq!(u16::from_u64(0xbeef).map(u16::to_u64), Some(0xbeef));
+        assert!(u16::alphabet().is_empty());
+        assert_eq!('é'.to_u64(), 0xe9);
+        assert_eq!(char::from_u64(0xe9), Some('é'));
+        assert_eq!(char::from_u64(0xd800), None);
+        assert_eq!(<u32 as Symbol>::NAME, "u32");
+
+        let (ids, lines) = intern("a\nb\na\nc".lines());
+        assert_eq!(ids, vec![0, 1, 0, 2]);
+        assert_eq!(lines, vec!["a", "b", "c"]);
+    }
+}
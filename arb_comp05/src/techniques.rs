@@ -5,19 +5,18 @@ mod tests {
 
     use crate::bpe::Bpe;
     use crate::matcher;
-    use crate::recode::{condense, expand, range};
     use crate::test_utils::{self, print_tokens};
-    use crate::token::{Token, TokenId};
+    use crate::token::TokenId;
+    use crate::token_set::TokenSet;
 
     #[test]
     fn technique01() {
         // Technique 1: to show pattern1 in terms of pattern2 diff
         // 1. train a BPE model on both patterns
         // 2. encode both patterns
-        // 3. get the range of all ids in and "reachable from" pattern2
-        // 4. expand pattern1 to its representation with smallest tokens (just bytes)
-        // 5. condense the result by merging tokens that are purely in or out of the pattern2 range
-        // 6. print the result with highlighting based on whether the token is in or out of the pattern2 range
+        // 3. re-tokenize pattern1 relative to pattern2 (`Bpe::retokenize_relative_to`): expand it to
+        //    bytes and condense by merging tokens that are purely in or out of the pattern2 range
+        // 4. print the result with highlighting based on whether the token is in or out of the pattern2 range
 
         let file1 = "Hello 12345 World";
         let file2 = "Hello World";
@@ -27,19 +26,17 @@ mod tests {
         let pattern1 = bpe.encode(file1.as_bytes());
         let pattern2 = bpe.encode(file2.as_bytes());
 
-        let in_pattern2 = range(pattern2.clone(), bpe.ids_to_tokens(), bpe.tokens_to_ids());
-
-        let merge_if = |id0: TokenId, id1: TokenId| -> Option<TokenId> {
-            bpe.tokens_to_ids()
-                .get(&Token::Merge(id0, id1))
-                .filter(|_| in_pattern2.contains(&id0) == in_pattern2.contains(&id1))
-                .copied()
-        };
-
-        let e = expand(pattern1.clone(), bpe.ids_to_tokens());
-        let c = condense(e.clone(), merge_if);
-
-        print_tokens(c, &bpe, |id| !in_pattern2.contains(id));
+        let spans = bpe.retokenize_relative_to(&pattern1, &pattern2);
+        let novel = (spans.iter())
+            .filter(|span| !span.is_shared())
+            .flat_map(|span| span.ids.iter().copied())
+            .collect::<TokenSet>();
+        let ids = spans
+            .into_iter()
+            .flat_map(|span| span.ids)
+            .collect::<Vec<_>>();
+
+        print_tokens(ids, &bpe, |id| novel.contains(id));
     }
 
     #[test]
@@ -60,11 +57,44 @@ mod tests {
 
         let matches = matcher::greedy00(&pattern1, &pattern2);
 
-        test_utils::print_ui_01(&matches, |x| bpe.decode(x.clone()), true);
+        test_utils::print_ui_01(&matches, |x| bpe.decode(x), true);
 
         //redundant output
         println!("print_ui_02 test");
-        let (cells0, cells1) = test_utils::matches_to_cells(&matches, |x| bpe.decode(x.clone()));
+        let (cells0, cells1) = test_utils::matches_to_cells(&matches, |x| bpe.decode(x));
         test_utils::print_ui_02(&cells0, &cells1);
     }
+
+    #[test]
+    fn technique03() {
+        // Technique 3: to show what a family of builds shares that one build lacks
+        // 1. train a BPE model on all builds
+        // 2. encode each build
+        // 3. combine the sets of ids reachable from each build: in A and C but not in B
+        // 4. print build A with highlighting on the tokens in that set
+
+        let builds = [
+            "version=3 flags=debug,trace body",
+            "version=4 flags=release body",
+            "version=5 flags=debug,trace body",
+        ];
+
+        let data = builds.map(str::as_bytes);
+        let bpe = Bpe::new(&data);
+        let patterns = data.map(|x| bpe.encode(x));
+        let reachable = |pattern: &[TokenId]| {
+            TokenSet::reachable(&[pattern], bpe.ids_to_tokens(), bpe.tokens_to_ids())
+        };
+        let [a, b, c] = [0, 1, 2].map(|i| reachable(&patterns[i]));
+
+        let not_in_b = &(&a & &c) - &b;
+        print_tokens(patterns[0].clone(), &bpe, |id| not_in_b.contains(id));
+
+        let bytes = not_in_b
+            .iter()
+            .flat_map(|&id| bpe.token_bytes(id).unwrap().iter().copied());
+        let bytes = bytes.collect::<Vec<_>>();
+        assert!(bytes.contains(&b'g'));
+        assert!(!bytes.contains(&b'v'));
+    }
 }
@@ -2,6 +2,7 @@ use crate::bpe::Bpe;
 use crate::matcher::Matched;
 use crate::token::TokenId;
 use colored::*;
+use std::collections::HashMap;
 
 fn color(c: usize, s: &str) -> ColoredString {
     match c % 6 {
@@ -33,7 +34,7 @@ pub fn print_tokens(
     highlight: impl Fn(&TokenId) -> bool,
 ) {
     for id in ids {
-        let s = format!("{:?}", bpe.decode(vec![id]));
+        let s = format!("{:?}", bpe.token_bytes(id).unwrap());
 
         let s = if highlight(&id) {
             color_highlight(id.0, &s)
@@ -77,9 +78,9 @@ fn print_columns(
 fn colored_hex(
     highlight: bool,
     id: TokenId,
-    decode: impl Fn(&Vec<TokenId>) -> Vec<u8>,
+    decode: impl Fn(&[TokenId]) -> Vec<u8>,
 ) -> Vec<ColoredString> {
-    decode(&vec![id])
+    decode(&[id])
         .iter()
         .map(|b| format!("{b:02x}"))
         .map(|s| {
@@ -96,9 +97,9 @@ fn colored_hex(
 fn colored_ascii(
     highlight: bool,
     id: TokenId,
-    decode: impl Fn(&Vec<TokenId>) -> Vec<u8>,
+    decode: impl Fn(&[TokenId]) -> Vec<u8>,
 ) -> Vec<ColoredString> {
-    decode(&vec![id])
+    decode(&[id])
         .iter()
         .map(|b| {
             format!(
@@ -151,7 +152,7 @@ fn print_colored_id_lists(highlight: bool, left: &[TokenId], right: &[TokenId])
 
 pub fn print_ui_01(
     matches: &[Matched],
-    decode: impl Fn(&Vec<TokenId>) -> Vec<u8>,
+    decode: impl Fn(&[TokenId]) -> Vec<u8>,
     print_token_ids: bool,
 ) {
     matches.iter().for_each(|matched| match matched {
@@ -185,63 +186,77 @@ pub fn print_ui_01(
 }
 
 //todo: dedup w/ colored_hex?
-fn hex_cells(diff: bool, id: TokenId, decode: impl Fn(&Vec<TokenId>) -> Vec<u8>) -> Vec<HexCell> {
-    decode(&vec![id])
-        .iter()
-        .map(|&b| {
-            if diff {
-                HexCell::Diff {
-                    value: b,
-                    source_id: id.0,
-                }
-            } else {
-                HexCell::Same {
-                    value: b,
-                    source_id: id.0,
-                }
+/// Appends cells for `bytes`, the bytes of `id`, which start at `offset` in their input.
+fn hex_cells(diff: bool, id: TokenId, bytes: &[u8], offset: &mut usize, cells: &mut Vec<HexCell>) {
+    cells.extend(bytes.iter().enumerate().map(|(i, &value)| {
+        let (source_id, offset) = (id.0, *offset + i);
+        if diff {
+            HexCell::Diff {
+                value,
+                source_id,
+                offset,
             }
-        })
-        .collect()
+        } else {
+            HexCell::Same {
+                value,
+                source_id,
+                offset,
+            }
+        }
+    }));
+    *offset += bytes.len();
 }
 
 //todo: dedup w/ print_ui_01?
+/// Hex cells for both sides of `matches`, with diff blocks padded to the same length.
+///
+/// `decode` is called once per distinct token.
 pub fn matches_to_cells(
     matches: &[Matched],
-    decode: impl Fn(&Vec<TokenId>) -> Vec<u8>,
+    decode: impl Fn(&[TokenId]) -> Vec<u8>,
 ) -> (Vec<HexCell>, Vec<HexCell>) {
     let mut cells0 = vec![];
     let mut cells1 = vec![];
+    let (mut offset0, mut offset1) = (0, 0);
 
-    matches.iter().for_each(|matched| match matched {
-        Matched::Same(ids) => {
-            for &id in ids {
-                cells0.append(&mut hex_cells(false, id, &decode));
-                cells1.append(&mut hex_cells(false, id, &decode));
-            }
-        }
-        Matched::Diff(ids0, ids1) => {
-            let mut block_cells0 = vec![];
-            let mut block_cells1 = vec![];
+    let mut token_bytes = HashMap::new();
+    let mut add_cells = |diff, id, offset: &mut usize, cells: &mut Vec<HexCell>| {
+        let bytes = token_bytes.entry(id).or_insert_with(|| decode(&[id]));
+        hex_cells(diff, id, bytes, offset, cells);
+    };
 
-            for &id in ids0 {
-                block_cells0.append(&mut hex_cells(true, id, &decode));
-            }
-            for &id in ids1 {
-                block_cells1.append(&mut hex_cells(true, id, &decode));
+    for matched in matches {
+        match matched {
+            Matched::Same(ids) => {
+                for &id in ids {
+                    add_cells(false, id, &mut offset0, &mut cells0);
+                    add_cells(false, id, &mut offset1, &mut cells1);
+                }
             }
+            Matched::Diff(ids0, ids1) => {
+                let mut block_cells0 = vec![];
+                let mut block_cells1 = vec![];
 
-            while block_cells0.len() < block_cells1.len() {
-                block_cells0.push(HexCell::Blank);
-            }
+                for &id in ids0 {
+                    add_cells(true, id, &mut offset0, &mut block_cells0);
+                }
+                for &id in ids1 {
+                    add_cells(true, id, &mut offset1, &mut block_cells1);
+                }
 
-            while block_cells1.len() < block_cells0.len() {
-                block_cells1.push(HexCell::Blank);
-            }
+                while block_cells0.len() < block_cells1.len() {
+                    block_cells0.push(HexCell::Blank);
+                }
+
+                while block_cells1.len() < block_cells0.len() {
+                    block_cells1.push(HexCell::Blank);
+                }
 
-            cells0.append(&mut block_cells0);
-            cells1.append(&mut block_cells1);
+                cells0.append(&mut block_cells0);
+                cells1.append(&mut block_cells1);
+            }
         }
-    });
+    }
 
     (cells0, cells1)
 }
@@ -249,20 +264,47 @@ pub fn matches_to_cells(
 // test interface for very lightweight frontend
 #[derive(Debug, Clone, Copy)]
 pub enum HexCell {
-    Same { value: u8, source_id: usize },
-    Diff { value: u8, source_id: usize },
+    /// `offset` is the position of `value` in its input.
+    ///
+    /// Cells may gain fields; match them with `..`.
+    #[non_exhaustive]
+    Same {
+        value: u8,
+        source_id: usize,
+        offset: usize,
+    },
+    #[non_exhaustive]
+    Diff {
+        value: u8,
+        source_id: usize,
+        offset: usize,
+    },
     Blank,
 }
 
+impl HexCell {
+    /// The position of the cell's byte in its input, or `None` for a blank.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            HexCell::Same { offset, .. } | HexCell::Diff { offset, .. } => Some(*offset),
+            HexCell::Blank => None,
+        }
+    }
+}
+
 pub fn print_ui_02(cells0: &[HexCell], cells1: &[HexCell]) {
     fn f(cell: &HexCell) -> ColoredString {
         match cell {
-            HexCell::Same { value, source_id } => {
+            HexCell::Same {
+                value, source_id, ..
+            } => {
                 let s = format!("{value:02x}");
                 assert_eq!(2, s.chars().count());
                 color(*source_id, &s)
             }
-            HexCell::Diff { value, source_id } => {
+            HexCell::Diff {
+                value, source_id, ..
+            } => {
                 let s = format!("{value:02x}");
                 assert_eq!(2, s.chars().count());
                 color_highlight(*source_id, &s)
@@ -291,7 +333,7 @@ mod tests {
             Matched::Same(token_ids1.clone()),
             Matched::Diff(token_ids0, token_ids1),
         ];
-        let decode = |x: &Vec<TokenId>| bpe.decode(x.clone());
+        let decode = |x: &[TokenId]| bpe.decode(x);
 
         print_ui_01(&matches, decode, true);
 
@@ -314,10 +356,17 @@ mod tests {
             Matched::Same(token_ids1.clone()),
             Matched::Diff(token_ids0, token_ids1),
         ];
-        let decode = |x: &Vec<TokenId>| bpe.decode(x.clone());
+        let decode = |x: &[TokenId]| bpe.decode(x);
 
         let (cells0, cells1) = matches_to_cells(&matches, decode);
 
         print_ui_02(&cells0, &cells1);
+
+        // offsets count bytes of each input, skipping blanks
+        let offsets =
+            |cells: &[HexCell]| cells.iter().filter_map(HexCell::offset).collect::<Vec<_>>();
+        assert_eq!(offsets(&cells0), (0..20).collect::<Vec<_>>());
+        assert_eq!(offsets(&cells1), (0..24).collect::<Vec<_>>());
+        assert_eq!(cells0.len(), cells1.len());
     }
 }
@@ -1,17 +1,25 @@
 use crate::pairs::ToPairs;
+#[cfg(test)]
 use crate::utils::{add_to_counts, increment};
+#[cfg(test)]
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct TokenId(pub usize);
 
+/// A vocabulary entry: a base symbol (a byte by default), or the merge of two earlier tokens
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-pub enum Token {
-    Byte(u8),
+pub enum Token<S = u8> {
+    /// Was `Byte(u8)`; saved vocabularies still load with that name.
+    #[serde(alias = "Byte")]
+    Symbol(S),
     Merge(TokenId, TokenId),
+    /// A run of this many (at least 2) copies of a symbol token
+    Run(TokenId, usize),
 }
 
+#[cfg(test)]
 fn count_id_pairs(ids: &[TokenId]) -> IndexMap<(TokenId, TokenId), usize> {
     let mut counts = IndexMap::new();
 
@@ -22,6 +30,8 @@ fn count_id_pairs(ids: &[TokenId]) -> IndexMap<(TokenId, TokenId), usize> {
     counts
 }
 
+/// Reference implementation of the pair selection in [`crate::pair_index::PairIndex`]
+#[cfg(test)]
 pub fn find_most_common_duplicate_id_pair<'a>(
     patterns: impl IntoIterator<Item = &'a Vec<TokenId>>,
 ) -> Option<((TokenId, TokenId), usize)> {
@@ -163,7 +173,7 @@ mod tests {
             merge(pattern.iter().copied(), f)
         };
 
-        let result = merge_tester(&vec![], TokenId(0), TokenId(1), TokenId(3));
+        let result = merge_tester(&[], TokenId(0), TokenId(1), TokenId(3));
         assert_eq!(result, vec![]);
 
         let pattern = vec![TokenId(0), TokenId(1), TokenId(2), TokenId(3)];
@@ -0,0 +1,213 @@
+//! Sets of tokens reachable from encoded patterns, for highlighting one input relative to others
+
+use crate::error::Result;
+use crate::recode::{try_range, IdsToTokens, TokensToIds};
+use crate::symbol::Symbol;
+use crate::token::TokenId;
+use indexmap::IndexSet;
+use std::ops::{BitAnd, BitOr, Sub};
+
+/// A set of `TokenId`s in insertion order, as returned by [`crate::recode::range`]
+///
+/// Combine sets with `&a | &b`, `&a & &b` and `&a - &b`, e.g. to find the tokens of builds A and C
+/// that build B does not have.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenSet {
+    ids: IndexSet<TokenId>,
+}
+
+impl TokenSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The tokens in and reachable from any of `patterns` (see [`crate::recode::range`]).
+    pub fn reachable<S: Symbol>(
+        patterns: &[&[TokenId]],
+        ids_to_tokens: &impl IdsToTokens<S>,
+        tokens_to_ids: &impl TokensToIds<S>,
+    ) -> Self {
+        Self::try_reachable(patterns, ids_to_tokens, tokens_to_ids)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn try_reachable<S: Symbol>(
+        patterns: &[&[TokenId]],
+        ids_to_tokens: &impl IdsToTokens<S>,
+        tokens_to_ids: &impl TokensToIds<S>,
+    ) -> Result<Self> {
+        let mut ids = IndexSet::new();
+        for pattern in patterns {
+            ids.extend(try_range(pattern.to_vec(), ids_to_tokens, tokens_to_ids)?);
+        }
+        Ok(Self { ids })
+    }
+
+    pub fn contains(&self, id: &TokenId) -> bool {
+        self.ids.contains(id)
+    }
+
+    pub fn insert(&mut self, id: TokenId) -> bool {
+        self.ids.insert(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn iter(&self) -> indexmap::set::Iter<'_, TokenId> {
+        self.ids.iter()
+    }
+
+    /// The tokens in either set: this set's order, then the other's new tokens.
+    pub fn union(&self, other: &TokenSet) -> TokenSet {
+        self.ids.union(&other.ids).copied().collect()
+    }
+
+    /// The tokens in both sets, in this set's order.
+    pub fn intersection(&self, other: &TokenSet) -> TokenSet {
+        self.ids.intersection(&other.ids).copied().collect()
+    }
+
+    /// The tokens in this set but not the other, in this set's order.
+    pub fn difference(&self, other: &TokenSet) -> TokenSet {
+        self.ids.difference(&other.ids).copied().collect()
+    }
+
+    pub fn into_index_set(self) -> IndexSet<TokenId> {
+        self.ids
+    }
+}
+
+impl From<IndexSet<TokenId>> for TokenSet {
+    fn from(ids: IndexSet<TokenId>) -> Self {
+        Self { ids }
+    }
+}
+
+impl FromIterator<TokenId> for TokenSet {
+    fn from_iter<I: IntoIterator<Item = TokenId>>(iter: I) -> Self {
+        Self {
+            ids: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<TokenId> for TokenSet {
+    fn extend<I: IntoIterator<Item = TokenId>>(&mut self, iter: I) {
+        self.ids.extend(iter);
+    }
+}
+
+impl IntoIterator for TokenSet {
+    type Item = TokenId;
+    type IntoIter = indexmap::set::IntoIter<TokenId>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ids.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a TokenSet {
+    type Item = &'a TokenId;
+    type IntoIter = indexmap::set::Iter<'a, TokenId>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ids.iter()
+    }
+}
+
+impl BitOr for &TokenSet {
+    type Output = TokenSet;
+
+    fn bitor(self, other: &TokenSet) -> TokenSet {
+        self.union(other)
+    }
+}
+
+impl BitAnd for &TokenSet {
+    type Output = TokenSet;
+
+    fn bitand(self, other: &TokenSet) -> TokenSet {
+        self.intersection(other)
+    }
+}
+
+impl Sub for &TokenSet {
+    type Output = TokenSet;
+
+    fn sub(self, other: &TokenSet) -> TokenSet {
+        self.difference(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpe::Bpe;
+    use crate::config::BpeConfig;
+    use crate::error::Error;
+    use crate::recode::range;
+    use crate::token::Token;
+
+    fn set(ids: &[usize]) -> TokenSet {
+        ids.iter().map(|&id| TokenId(id)).collect()
+    }
+
+    #[test]
+    fn test_token_set() {
+        let (a, b) = (set(&[3, 1, 2]), set(&[2, 4, 3]));
+        assert_eq!(
+            (&a | &b).into_iter().collect::<Vec<_>>(),
+            set(&[3, 1, 2, 4]).into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(&a & &b, set(&[3, 2]));
+        assert_eq!(&a - &b, set(&[1]));
+        assert_eq!(&b - &a, set(&[4]));
+        assert!((&a - &a).is_empty());
+
+        let bpe = Bpe::new(&[b"abcabc", b"abxabx", b"xyxy"]);
+        let encode = |x: &[u8]| bpe.encode(x);
+        let (p0, p1, p2) = (encode(b"abcabc"), encode(b"abxabx"), encode(b"xyxy"));
+        let reachable = |patterns: &[&[TokenId]]| {
+            TokenSet::reachable(patterns, bpe.ids_to_tokens(), bpe.tokens_to_ids())
+        };
+
+        let in_0 = reachable(&[&p0]);
+        assert_eq!(
+            in_0,
+            range(p0.clone(), bpe.ids_to_tokens(), bpe.tokens_to_ids()).into()
+        );
+        assert_eq!(reachable(&[&p0, &p2]), &in_0 | &reachable(&[&p2]));
+
+        // "ab" is in the first two, "x" in the last two
+        let ab = bpe.tokens_to_ids()[&Token::Merge(TokenId(b'a' as usize), TokenId(b'b' as usize))];
+        let both = &reachable(&[&p0]) & &reachable(&[&p1]);
+        assert!(both.contains(&ab));
+        assert!(!both.contains(&TokenId(b'x' as usize)));
+        let only_1 = &(&reachable(&[&p1]) - &reachable(&[&p0])) - &reachable(&[&p2]);
+        assert!(only_1.iter().all(|id| !in_0.contains(id)));
+        assert!(!only_1.contains(&TokenId(b'x' as usize)));
+
+        // a run token is only reachable from a pattern that has it
+        let data = [b"ab".as_slice(), &[0; 10], b"ab", &[0; 10]].concat();
+        let bpe = Bpe::with_config(&[&data], &BpeConfig::new().min_run_len(4));
+        let run = bpe.tokens_to_ids()[&Token::Run(TokenId(0), 10)];
+        let reachable = |data: &[u8]| {
+            let pattern = bpe.encode(data);
+            TokenSet::reachable(&[&pattern], bpe.ids_to_tokens(), bpe.tokens_to_ids())
+        };
+        assert!(reachable(&data).contains(&run));
+        assert!(reachable(&[0; 10]).contains(&run));
+        assert!(!reachable(&[0; 9]).contains(&run));
+
+        assert!(matches!(
+            TokenSet::try_reachable(&[&[TokenId(999)]], bpe.ids_to_tokens(), bpe.tokens_to_ids()),
+            Err(Error::UnknownTokenId(TokenId(999)))
+        ));
+    }
+}
@@ -0,0 +1,264 @@
+//! Segmentation strategies that turn bytes into tokens for [`crate::matcher`]
+//!
+//! A [`Bpe`] learns its tokens from training data; the chunkers here cut inputs without
+//! training and number each distinct chunk as they first meet it, so equal chunks in
+//! different inputs get the same id.
+
+use crate::bpe::{Bpe, EncodeMode};
+use crate::error::Error;
+use crate::recode::TokenSpan;
+use crate::token::TokenId;
+use std::collections::HashMap;
+
+pub trait Tokenizer {
+    /// Splits `data` into tokens; the same bytes always give the same ids.
+    fn encode(&mut self, data: &[u8]) -> Vec<TokenId>;
+
+    /// The bytes of tokens from `encode`.
+    ///
+    /// Panics on ids this tokenizer did not produce.
+    fn decode(&self, ids: &[TokenId]) -> Vec<u8>;
+
+    /// As `encode`, pairing each token with the range of `data` it covers.
+    fn encode_with_spans(&mut self, data: &[u8]) -> Vec<TokenSpan> {
+        let mut offset = 0;
+        (self.encode(data).into_iter())
+            .map(|id| {
+                let len = self.decode(&[id]).len();
+                offset += len;
+                (id, offset - len..offset)
+            })
+            .collect()
+    }
+}
+
+/// Greedy encoding, as [`Bpe::encode`]
+impl Tokenizer for Bpe {
+    fn encode(&mut self, data: &[u8]) -> Vec<TokenId> {
+        Bpe::encode(self, data)
+    }
+
+    fn decode(&self, ids: &[TokenId]) -> Vec<u8> {
+        Bpe::decode(self, ids)
+    }
+
+    fn encode_with_spans(&mut self, data: &[u8]) -> Vec<TokenSpan> {
+        Bpe::encode_with_spans(self, data, EncodeMode::Greedy)
+    }
+}
+
+/// A [`Bpe`] encoding with a chosen [`EncodeMode`]
+pub struct BpeTokenizer<'a> {
+    pub bpe: &'a Bpe,
+    pub mode: EncodeMode,
+}
+
+impl Tokenizer for BpeTokenizer<'_> {
+    fn encode(&mut self, data: &[u8]) -> Vec<TokenId> {
+        self.bpe.encode_with(data, self.mode)
+    }
+
+    fn decode(&self, ids: &[TokenId]) -> Vec<u8> {
+        self.bpe.decode(ids)
+    }
+
+    fn encode_with_spans(&mut self, data: &[u8]) -> Vec<TokenSpan> {
+        self.bpe.encode_with_spans(data, self.mode)
+    }
+}
+
+/// Distinct chunks, numbered in order of first appearance
+#[derive(Debug, Clone, Default)]
+struct ChunkTable {
+    ids: HashMap<Vec<u8>, TokenId>,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl ChunkTable {
+    fn intern(&mut self, chunk: &[u8]) -> TokenId {
+        if let Some(&id) = self.ids.get(chunk) {
+            return id;
+        }
+        let id = TokenId(self.chunks.len());
+        self.ids.insert(chunk.to_vec(), id);
+        self.chunks.push(chunk.to_vec());
+        id
+    }
+
+    fn decode(&self, ids: &[TokenId]) -> Vec<u8> {
+        (ids.iter())
+            .flat_map(|&id| match self.chunks.get(id.0) {
+                Some(chunk) => chunk,
+                None => panic!("{}", Error::UnknownTokenId(id)),
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// Chunks of a fixed number of bytes (the last one may be shorter)
+#[derive(Debug, Clone)]
+pub struct FixedChunks {
+    width: usize,
+    chunks: ChunkTable,
+}
+
+impl FixedChunks {
+    pub fn new(width: usize) -> Self {
+        Self {
+            width: width.max(1),
+            chunks: ChunkTable::default(),
+        }
+    }
+}
+
+impl Tokenizer for FixedChunks {
+    fn encode(&mut self, data: &[u8]) -> Vec<TokenId> {
+        (data.chunks(self.width))
+            .map(|chunk| self.chunks.intern(chunk))
+            .collect()
+    }
+
+    fn decode(&self, ids: &[TokenId]) -> Vec<u8> {
+        self.chunks.decode(ids)
+    }
+}
+
+/// Random values the Gear hash adds for each byte (splitmix64 from seed 0).
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Content-defined chunks, cut where a Gear rolling hash of the last bytes has its top bits clear
+///
+/// Cut points depend only on nearby content, so an insertion or deletion changes the chunks
+/// around it and the chunking falls back in step after, unlike with [`FixedChunks`].
+#[derive(Debug, Clone)]
+pub struct GearChunks {
+    min_len: usize,
+    max_len: usize,
+    mask: u64,
+    chunks: ChunkTable,
+}
+
+impl GearChunks {
+    /// Chunks of about `avg_len` bytes past a minimum of a quarter of that,
+    /// and at most four times that; `avg_len` is rounded up to a power of two.
+    pub fn new(avg_len: usize) -> Self {
+        let avg_len = avg_len.max(1).next_power_of_two();
+        Self {
+            min_len: (avg_len / 4).max(1),
+            max_len: avg_len * 4,
+            mask: !(u64::MAX >> avg_len.trailing_zeros()),
+            chunks: ChunkTable::default(),
+        }
+    }
+
+    /// The length of the chunk at the start of `data`.
+    fn chunk_len(&self, data: &[u8]) -> usize {
+        let end = data.len().min(self.max_len);
+        let mut hash = 0u64;
+        for (i, &b) in data[..end].iter().enumerate() {
+            hash = (hash << 1).wrapping_add(GEAR[b as usize]);
+            if i + 1 >= self.min_len && hash & self.mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+}
+
+impl Tokenizer for GearChunks {
+    fn encode(&mut self, mut data: &[u8]) -> Vec<TokenId> {
+        let mut ids = vec![];
+        while !data.is_empty() {
+            let (chunk, rest) = data.split_at(self.chunk_len(data));
+            ids.push(self.chunks.intern(chunk));
+            data = rest;
+        }
+        ids
+    }
+
+    fn decode(&self, ids: &[TokenId]) -> Vec<u8> {
+        self.chunks.decode(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{greedy00, Matched};
+    use rand::{Rng, SeedableRng};
+
+    /// Bytes in the diff blocks of `tokenizer`'s encodings of `a` and `b`.
+    fn diff_len(tokenizer: &mut dyn Tokenizer, a: &[u8], b: &[u8]) -> usize {
+        let (ids0, ids1) = (tokenizer.encode(a), tokenizer.encode(b));
+        assert_eq!(tokenizer.decode(&ids0), a);
+        assert_eq!(tokenizer.decode(&ids1), b);
+
+        (greedy00(&ids0, &ids1).iter())
+            .map(|m| match m {
+                Matched::Same(_) => 0,
+                Matched::Diff(ids0, ids1) => {
+                    tokenizer.decode(ids0).len() + tokenizer.decode(ids1).len()
+                }
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_tokenizers() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let a = (0..8000).map(|_| rng.gen()).collect::<Vec<u8>>();
+        let mut b = a.clone();
+        b.insert(2000, 0xff);
+
+        let mut fixed = FixedChunks::new(64);
+        let ids = fixed.encode(&[1, 2, 3, 1, 2, 3, 1]);
+        assert_eq!(ids, vec![TokenId(0)]);
+        let mut fixed = FixedChunks::new(3);
+        let ids = fixed.encode(&[1, 2, 3, 1, 2, 3, 1]);
+        assert_eq!(ids, [0, 0, 1].map(TokenId));
+        assert_eq!(fixed.encode(&[1]), vec![TokenId(1)]);
+
+        // fixed chunks never line up again after the insertion; content-defined ones do
+        // within a few chunks (short chunks' cuts still depend on where they start)
+        assert!(diff_len(&mut FixedChunks::new(64), &a, &b) > 12000);
+        let mut gear = GearChunks::new(64);
+        assert!(diff_len(&mut gear, &a, &b) < 2000);
+
+        let lens = (gear.chunks.chunks.iter())
+            .map(Vec::len)
+            .collect::<Vec<_>>();
+        assert!(lens.iter().all(|&len| (16..=256).contains(&len)));
+        let avg = lens.iter().sum::<usize>() / lens.len();
+        assert!((40..=120).contains(&avg), "{avg}");
+
+        let mut bpe = Bpe::new(&[&a[..1000], &b[..1000]]);
+        assert_eq!(diff_len(&mut bpe, &a[..1000], &b[..1000]), 0);
+        let ranked = &mut BpeTokenizer {
+            bpe: &bpe,
+            mode: EncodeMode::Ranked,
+        };
+        assert_eq!(ranked.encode(b"abc"), bpe.encode_ranked(b"abc"));
+
+        let spans = gear.encode_with_spans(&b);
+        assert_eq!(spans.last().unwrap().1.end, b.len());
+        for (id, range) in spans {
+            assert_eq!(gear.decode(&[id]), &b[range]);
+        }
+        let spans = ranked.encode_with_spans(&b[..1000]);
+        assert_eq!(spans, bpe.encode_with_spans(&b[..1000], EncodeMode::Ranked));
+    }
+}
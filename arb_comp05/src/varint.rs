@@ -0,0 +1,63 @@
+//! LEB128 variable-length unsigned integers
+
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Reads a varint from the front of `data` and advances past it.
+///
+/// Returns `None` if `data` ends early or the value does not fit in a `u64`.
+pub fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+
+        let bits = u64::from(byte & 0x7f);
+        if shift == 63 && bits > 1 {
+            return None;
+        }
+        value |= bits << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 255, 300, 16384, u32::MAX as u64, u64::MAX] {
+            let mut out = vec![];
+            write_varint(&mut out, value);
+
+            let mut data = out.as_slice();
+            assert_eq!(read_varint(&mut data), Some(value));
+            assert!(data.is_empty());
+        }
+
+        let mut out = vec![];
+        write_varint(&mut out, 300);
+        assert_eq!(out, vec![0xac, 0x02]);
+
+        let mut data: &[u8] = &[0x80];
+        assert_eq!(read_varint(&mut data), None);
+
+        let mut data: &[u8] = &[0xff; 10];
+        assert_eq!(read_varint(&mut data), None);
+    }
+}
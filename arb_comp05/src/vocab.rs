@@ -0,0 +1,248 @@
+//! Token storage for a [`crate::bpe::Bpe`], indexed by its dense `TokenId`s
+
+use crate::recode::{IdsToTokens, TokensToIds};
+use crate::symbol::Symbol;
+use crate::token::{Token, TokenId};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::OnceLock;
+
+/// Symbols below this value are found by indexing rather than hashing.
+const DENSE_SYMBOLS: u64 = 1 << 16;
+
+/// A multiplicative hash (as in rustc's `FxHasher`) for ids and symbols, which are small
+/// integers and don't need the collision resistance of the default hasher
+#[derive(Default)]
+pub(crate) struct IdHasher(u64);
+
+impl Hasher for IdHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        bytes.iter().for_each(|&b| self.write_u64(b.into()));
+    }
+
+    fn write_u8(&mut self, x: u8) {
+        self.write_u64(x.into());
+    }
+
+    fn write_u16(&mut self, x: u16) {
+        self.write_u64(x.into());
+    }
+
+    fn write_u32(&mut self, x: u32) {
+        self.write_u64(x.into());
+    }
+
+    fn write_u64(&mut self, x: u64) {
+        self.0 = (self.0.rotate_left(5) ^ x).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+
+    fn write_usize(&mut self, x: usize) {
+        self.write_u64(x as u64);
+    }
+}
+
+type IdMap<K> = HashMap<K, TokenId, BuildHasherDefault<IdHasher>>;
+
+/// Tokens in id order, with maps from symbols, merged pairs and runs back to their ids
+#[derive(Debug, Clone)]
+pub(crate) struct Vocab<S> {
+    tokens: Vec<Token<S>>,
+    /// Ids of symbols below [`DENSE_SYMBOLS`], by value.
+    dense_symbols: Vec<Option<TokenId>>,
+    symbols: IdMap<S>,
+    merges: IdMap<(TokenId, TokenId)>,
+    /// Run tokens by (symbol token, run length).
+    runs: BTreeMap<(TokenId, usize), TokenId>,
+    /// Number of symbols of each token, saturating at `usize::MAX`.
+    lens: Vec<usize>,
+    /// The symbols of each token, filled in when that token is first expanded.
+    expansions: Vec<OnceLock<Box<[S]>>>,
+}
+
+impl<S: Symbol> Vocab<S> {
+    pub fn new() -> Self {
+        Self {
+            tokens: vec![],
+            dense_symbols: vec![],
+            symbols: IdMap::default(),
+            merges: IdMap::default(),
+            runs: BTreeMap::new(),
+            lens: vec![],
+            expansions: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn tokens(&self) -> &[Token<S>] {
+        &self.tokens
+    }
+
+    pub fn runs(&self) -> &BTreeMap<(TokenId, usize), TokenId> {
+        &self.runs
+    }
+
+    /// Adds a token with the next id.
+    pub fn push(&mut self, token: Token<S>) -> TokenId {
+        let id = TokenId(self.tokens.len());
+        self.tokens.push(token);
+        self.lens.push(match token {
+            Token::Symbol(_) => 1,
+            Token::Merge(id0, id1) => self.lens[id0.0].saturating_add(self.lens[id1.0]),
+            Token::Run(symbol, len) => self.lens[symbol.0].saturating_mul(len),
+        });
+        self.expansions.push(OnceLock::new());
+        match token {
+            Token::Symbol(x) if x.to_u64() < DENSE_SYMBOLS => {
+                let i = x.to_u64() as usize;
+                if self.dense_symbols.len() <= i {
+                    self.dense_symbols.resize(i + 1, None);
+                }
+                self.dense_symbols[i] = Some(id);
+            }
+            Token::Symbol(x) => {
+                self.symbols.insert(x, id);
+            }
+            Token::Merge(id0, id1) => {
+                self.merges.insert((id0, id1), id);
+            }
+            Token::Run(symbol, len) => {
+                self.runs.insert((symbol, len), id);
+            }
+        }
+        id
+    }
+
+    pub fn get(&self, id: TokenId) -> Option<Token<S>> {
+        self.tokens.get(id.0).copied()
+    }
+
+    pub fn symbol(&self, x: S) -> Option<TokenId> {
+        match x.to_u64() {
+            i if i < DENSE_SYMBOLS => self.dense_symbols.get(i as usize).copied().flatten(),
+            _ => self.symbols.get(&x).copied(),
+        }
+    }
+
+    pub fn merge(&self, id0: TokenId, id1: TokenId) -> Option<TokenId> {
+        self.merges.get(&(id0, id1)).copied()
+    }
+
+    /// The number of symbols `id` expands to, without expanding it.
+    pub fn expansion_len(&self, id: TokenId) -> Option<usize> {
+        self.lens.get(id.0).copied()
+    }
+
+    /// The symbols `id` expands to, or `None` for an unknown id.
+    ///
+    /// Only the requested token is kept, not the tokens it was expanded from, so the memory
+    /// taken is that of the symbols of the tokens asked for.
+    pub fn expansion(&self, id: TokenId) -> Option<&[S]> {
+        let expansion = self.expansions.get(id.0)?;
+        Some(expansion.get_or_init(|| self.expand(id).into_boxed_slice()))
+    }
+
+    fn expand(&self, id: TokenId) -> Vec<S> {
+        // a stack rather than recursion, since a chain of merges can be as deep as the vocab
+        let mut symbols = Vec::with_capacity(self.lens[id.0]);
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(expansion) = self.expansions[id.0].get() {
+                symbols.extend_from_slice(expansion);
+                continue;
+            }
+            match self.tokens[id.0] {
+                Token::Symbol(x) => symbols.push(x),
+                Token::Merge(id0, id1) => stack.extend([id1, id0]),
+                Token::Run(symbol, len) => {
+                    let symbol = self.expand(symbol);
+                    for _ in 0..len {
+                        symbols.extend_from_slice(&symbol);
+                    }
+                }
+            }
+        }
+        symbols
+    }
+}
+
+impl<S: Symbol> IdsToTokens<S> for Vocab<S> {
+    fn token(&self, id: TokenId) -> Option<Token<S>> {
+        self.get(id)
+    }
+}
+
+impl<S: Symbol> TokensToIds<S> for Vocab<S> {
+    fn id(&self, token: &Token<S>) -> Option<TokenId> {
+        match *token {
+            Token::Symbol(x) => self.symbol(x),
+            Token::Merge(id0, id1) => self.merge(id0, id1),
+            Token::Run(symbol, len) => self.runs.get(&(symbol, len)).copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vocab() {
+        let mut vocab = Vocab::<u32>::new();
+        let a = vocab.push(Token::Symbol(7));
+        let b = vocab.push(Token::Symbol(1 << 20));
+        let ab = vocab.push(Token::Merge(a, b));
+        let run = vocab.push(Token::Run(a, 3));
+        let ab_run = vocab.push(Token::Merge(ab, run));
+
+        assert_eq!([a, b, ab, run, ab_run].map(|id| id.0), [0, 1, 2, 3, 4]);
+        assert_eq!(vocab.symbol(7), Some(a));
+        assert_eq!(vocab.symbol(1 << 20), Some(b));
+        assert_eq!(vocab.symbol(8), None);
+        assert_eq!(vocab.merge(a, b), Some(ab));
+        assert_eq!(vocab.merge(b, a), None);
+        for (i, token) in vocab.tokens().iter().enumerate() {
+            assert_eq!(vocab.id(token), Some(TokenId(i)));
+            assert_eq!(vocab.token(TokenId(i)), Some(*token));
+        }
+
+        assert_eq!(vocab.expansion(ab_run), Some(&[7, 1 << 20, 7, 7, 7][..]));
+        assert_eq!(vocab.expansion(TokenId(5)), None);
+        let abb = vocab.push(Token::Merge(ab, b));
+        assert_eq!(vocab.expansion(abb), Some(&[7, 1 << 20, 1 << 20][..]));
+        assert_eq!(vocab.expansion_len(ab_run), Some(5));
+        assert_eq!(vocab.expansion_len(TokenId(6)), None);
+    }
+
+    #[test]
+    fn test_expansion_cache() {
+        // a chain of merges, each one symbol longer than the last
+        let mut vocab = Vocab::<u8>::new();
+        let a = vocab.push(Token::Symbol(0));
+        let mut id = a;
+        for _ in 0..1000 {
+            id = vocab.push(Token::Merge(id, a));
+        }
+        let cached = |vocab: &Vocab<u8>| -> usize {
+            (vocab.expansions.iter())
+                .filter_map(|expansion| expansion.get().map(|symbols| symbols.len()))
+                .sum()
+        };
+
+        // only the requested tokens are kept, not every prefix on the way
+        assert_eq!(vocab.expansion(id).map(<[u8]>::len), Some(1001));
+        assert_eq!(cached(&vocab), 1001);
+        assert_eq!(vocab.expansion(TokenId(10)).map(<[u8]>::len), Some(11));
+        assert_eq!(cached(&vocab), 1012);
+
+        let run = vocab.push(Token::Run(a, usize::MAX));
+        assert_eq!(vocab.expansion_len(run), Some(usize::MAX));
+        assert_eq!(cached(&vocab), 1012);
+    }
+}
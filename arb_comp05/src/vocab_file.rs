@@ -0,0 +1,510 @@
+//! On-disk formats for trained [`Bpe`] vocabularies
+//!
+//! A vocabulary is stored as its token list in id order:
+//! the token at index `i` has `TokenId(i)`.
+//!
+//! * JSON: `{"version":4,"symbols":"u8","tokens":[{"Symbol":0},...,{"Merge":[97,98]},{"Run":[0,64]}],`
+//!   `"boundaries":"Newlines","min_run_len":16}`
+//! * binary: [`MAGIC`], a version byte, the symbol type name ([`Symbol::NAME`]) as a varint
+//!   length and UTF-8, a varint token count,
+//!   then per token a tag byte (`0`: symbol, `1`: merge, `2`: run) followed by
+//!   the varint symbol value ([`Symbol::to_u64`]), two varint token ids,
+//!   or the varint symbol token id and run length (at most [`MAX_RUN_LEN`]),
+//!   then the boundary rule as a tag byte and its varint parameters,
+//!   then the varint minimum run length (`0` for no run tokens).
+//!
+//! Version 3 files have no run tokens or minimum run length.
+//! Version 1 and 2 files are byte vocabularies, with symbol tokens written as `Byte`
+//! in JSON and as a single byte in binary. Version 1 files have no boundary rule,
+//! and load with [`Boundaries::None`].
+
+use crate::bpe::Bpe;
+use crate::error::{Error, Result};
+use crate::pretokenize::Boundaries;
+use crate::symbol::Symbol;
+use crate::token::{Token, TokenId};
+use crate::varint::{read_varint, write_varint};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+pub const MAGIC: &[u8; 8] = b"ACBPEVOC";
+pub const VERSION: u32 = 4;
+const SUPPORTED_VERSIONS: std::ops::RangeInclusive<u32> = 1..=VERSION;
+
+/// The longest run token a vocabulary may have; longer runs are encoded with several.
+pub const MAX_RUN_LEN: usize = 1 << 24;
+
+const TAG_SYMBOL: u8 = 0;
+const TAG_MERGE: u8 = 1;
+const TAG_RUN: u8 = 2;
+
+const TAG_BOUNDARIES_NONE: u8 = 0;
+const TAG_BOUNDARIES_POSITIONS: u8 = 1;
+const TAG_BOUNDARIES_EVERY_N: u8 = 2;
+const TAG_BOUNDARIES_NEWLINES: u8 = 3;
+const TAG_BOUNDARIES_ZERO_RUNS: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VocabFormat {
+    Json,
+    Binary,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "S: Symbol")]
+struct VocabJson<S> {
+    version: u32,
+    #[serde(default = "byte_symbols")]
+    symbols: String,
+    tokens: Vec<Token<S>>,
+    #[serde(default)]
+    boundaries: Boundaries,
+    #[serde(default)]
+    min_run_len: Option<usize>,
+}
+
+fn byte_symbols() -> String {
+    u8::NAME.to_string()
+}
+
+fn invalid_data(msg: impl Into<String>) -> Error {
+    Error::InvalidVocabulary(msg.into())
+}
+
+pub fn write<S: Symbol>(bpe: &Bpe<S>, mut writer: impl Write, format: VocabFormat) -> Result<()> {
+    let tokens = bpe.tokens().to_vec();
+
+    match format {
+        VocabFormat::Json => {
+            let file = VocabJson {
+                version: VERSION,
+                symbols: S::NAME.to_string(),
+                tokens,
+                boundaries: bpe.boundaries().clone(),
+                min_run_len: bpe.min_run_len(),
+            };
+            serde_json::to_writer(&mut writer, &file)?;
+        }
+        VocabFormat::Binary => {
+            let mut out = MAGIC.to_vec();
+            out.push(VERSION as u8);
+            write_varint(&mut out, S::NAME.len() as u64);
+            out.extend(S::NAME.as_bytes());
+            write_varint(&mut out, tokens.len() as u64);
+
+            for token in tokens {
+                match token {
+                    Token::Symbol(x) => {
+                        out.push(TAG_SYMBOL);
+                        write_varint(&mut out, x.to_u64());
+                    }
+                    Token::Merge(id0, id1) => {
+                        out.push(TAG_MERGE);
+                        write_varint(&mut out, id0.0 as u64);
+                        write_varint(&mut out, id1.0 as u64);
+                    }
+                    Token::Run(id, len) => {
+                        out.push(TAG_RUN);
+                        write_varint(&mut out, id.0 as u64);
+                        write_varint(&mut out, len as u64);
+                    }
+                }
+            }
+            write_boundaries(&mut out, bpe.boundaries());
+            write_varint(&mut out, bpe.min_run_len().unwrap_or(0) as u64);
+            writer.write_all(&out)?;
+        }
+    }
+    Ok(writer.flush()?)
+}
+
+/// Reads a vocabulary in either format, detected from the leading bytes.
+///
+/// The vocabulary must have been saved from a `Bpe<S>` with the same symbol type.
+pub fn read<S: Symbol>(mut reader: impl Read) -> Result<Bpe<S>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+
+    let (tokens, boundaries, min_run_len) = if data.starts_with(MAGIC) {
+        read_binary(&data[MAGIC.len()..])?
+    } else {
+        // check the symbol type before the tokens are parsed as `S`
+        #[derive(Deserialize)]
+        struct Header {
+            version: u32,
+            #[serde(default = "byte_symbols")]
+            symbols: String,
+        }
+        let header: Header = serde_json::from_slice(&data)?;
+        check_version(header.version)?;
+        check_symbols::<S>(&header.symbols)?;
+
+        let file: VocabJson<S> = serde_json::from_slice(&data)?;
+        (file.tokens, file.boundaries, file.min_run_len)
+    };
+
+    validate(&tokens)?;
+    Ok(Bpe::from_tokens(tokens, boundaries, min_run_len))
+}
+
+fn check_version(version: u32) -> Result<()> {
+    if SUPPORTED_VERSIONS.contains(&version) {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "unsupported vocabulary version {version}"
+        )))
+    }
+}
+
+fn check_symbols<S: Symbol>(name: &str) -> Result<()> {
+    if name == S::NAME {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "vocabulary of {name} symbols, expected {}",
+            S::NAME
+        )))
+    }
+}
+
+fn write_boundaries(out: &mut Vec<u8>, boundaries: &Boundaries) {
+    match boundaries {
+        Boundaries::None => out.push(TAG_BOUNDARIES_NONE),
+        Boundaries::Positions(positions) => {
+            out.push(TAG_BOUNDARIES_POSITIONS);
+            write_varint(out, positions.len() as u64);
+            positions.iter().for_each(|&x| write_varint(out, x as u64));
+        }
+        Boundaries::EveryN(n) => {
+            out.push(TAG_BOUNDARIES_EVERY_N);
+            write_varint(out, *n as u64);
+        }
+        Boundaries::Newlines => out.push(TAG_BOUNDARIES_NEWLINES),
+        Boundaries::ZeroRuns => out.push(TAG_BOUNDARIES_ZERO_RUNS),
+    }
+}
+
+fn read_boundaries(data: &mut &[u8]) -> Result<Boundaries> {
+    let truncated = || invalid_data("truncated vocabulary");
+    let read_usize = |data: &mut &[u8]| {
+        read_varint(data)
+            .and_then(|x| usize::try_from(x).ok())
+            .ok_or_else(truncated)
+    };
+
+    let (&tag, rest) = data.split_first().ok_or_else(truncated)?;
+    *data = rest;
+
+    Ok(match tag {
+        TAG_BOUNDARIES_NONE => Boundaries::None,
+        TAG_BOUNDARIES_POSITIONS => {
+            let count = read_usize(data)?;
+            let positions = (0..count)
+                .map(|_| read_usize(data))
+                .collect::<Result<_>>()?;
+            Boundaries::Positions(positions)
+        }
+        TAG_BOUNDARIES_EVERY_N => Boundaries::EveryN(read_usize(data)?),
+        TAG_BOUNDARIES_NEWLINES => Boundaries::Newlines,
+        TAG_BOUNDARIES_ZERO_RUNS => Boundaries::ZeroRuns,
+        _ => return Err(invalid_data(format!("unknown boundary tag {tag}"))),
+    })
+}
+
+fn read_binary<S: Symbol>(mut data: &[u8]) -> Result<(Vec<Token<S>>, Boundaries, Option<usize>)> {
+    let truncated = || invalid_data("truncated vocabulary");
+    let read_id = |data: &mut &[u8]| {
+        read_varint(data)
+            .and_then(|x| usize::try_from(x).ok())
+            .map(TokenId)
+            .ok_or_else(truncated)
+    };
+
+    let (&version, rest) = data.split_first().ok_or_else(truncated)?;
+    data = rest;
+    check_version(u32::from(version))?;
+
+    if version >= 3 {
+        let len = read_varint(&mut data)
+            .and_then(|x| usize::try_from(x).ok())
+            .filter(|&len| len <= data.len())
+            .ok_or_else(truncated)?;
+        let (name, rest) = data.split_at(len);
+        data = rest;
+        check_symbols::<S>(&String::from_utf8_lossy(name))?;
+    } else {
+        check_symbols::<S>(u8::NAME)?;
+    }
+
+    let count = read_varint(&mut data).ok_or_else(truncated)?;
+    let mut tokens = vec![];
+
+    for _ in 0..count {
+        let (&tag, rest) = data.split_first().ok_or_else(truncated)?;
+        data = rest;
+
+        let token = match tag {
+            TAG_SYMBOL if version >= 3 => {
+                let x = read_varint(&mut data).ok_or_else(truncated)?;
+                let symbol = S::from_u64(x)
+                    .ok_or_else(|| invalid_data(format!("symbol {x:#x} out of range")))?;
+                Token::Symbol(symbol)
+            }
+            TAG_SYMBOL => {
+                let (&b, rest) = data.split_first().ok_or_else(truncated)?;
+                data = rest;
+                Token::Symbol(S::from_u64(b.into()).ok_or_else(truncated)?)
+            }
+            TAG_MERGE => Token::Merge(read_id(&mut data)?, read_id(&mut data)?),
+            TAG_RUN if version >= 4 => Token::Run(read_id(&mut data)?, read_id(&mut data)?.0),
+            _ => return Err(invalid_data(format!("unknown token tag {tag}"))),
+        };
+        tokens.push(token);
+    }
+
+    let boundaries = if version >= 2 {
+        read_boundaries(&mut data)?
+    } else {
+        Boundaries::None
+    };
+    let min_run_len = if version >= 4 {
+        Some(read_id(&mut data)?.0).filter(|&len| len > 0)
+    } else {
+        None
+    };
+
+    if !data.is_empty() {
+        return Err(invalid_data("trailing data after vocabulary"));
+    }
+    Ok((tokens, boundaries, min_run_len))
+}
+
+/// Checks that `tokens` (in id order) form a vocabulary `Bpe` can use:
+/// every symbol of [`Symbol::alphabet`] appears, merges only refer to earlier ids,
+/// runs repeat an earlier symbol token at least twice, and no token is defined twice.
+fn validate<S: Symbol>(tokens: &[Token<S>]) -> Result<()> {
+    let mut seen = std::collections::HashSet::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Symbol(x) => {
+                if !seen.insert(*token) {
+                    return Err(invalid_data(format!(
+                        "symbol token {:#04x} defined twice",
+                        x.to_u64()
+                    )));
+                }
+            }
+            Token::Merge(id0, id1) => {
+                if id0.0 >= i || id1.0 >= i {
+                    return Err(invalid_data(format!(
+                        "merge {i} refers to a later token ({}, {})",
+                        id0.0, id1.0
+                    )));
+                }
+                if !seen.insert(*token) {
+                    return Err(invalid_data(format!("merge {i} defined twice")));
+                }
+            }
+            Token::Run(id, len) => {
+                if !matches!(tokens.get(id.0), Some(Token::Symbol(_))) || id.0 >= i || len < 2 {
+                    return Err(invalid_data(format!(
+                        "run {i} is not of an earlier symbol ({}, {len})",
+                        id.0
+                    )));
+                }
+                if len > MAX_RUN_LEN {
+                    return Err(invalid_data(format!(
+                        "run {i} is longer than {MAX_RUN_LEN} ({len})"
+                    )));
+                }
+                if !seen.insert(*token) {
+                    return Err(invalid_data(format!("run {i} defined twice")));
+                }
+            }
+        }
+    }
+
+    if let Some(x) = (S::alphabet().into_iter()).find(|&x| !seen.contains(&Token::Symbol(x))) {
+        return Err(invalid_data(format!(
+            "symbol token {:#04x} missing",
+            x.to_u64()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BpeConfig;
+
+    fn round_trip(bpe: &Bpe, format: VocabFormat) -> Bpe {
+        let mut out = vec![];
+        write(bpe, &mut out, format).unwrap();
+        read(out.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bpe = Bpe::new(&[&[1, 2, 3, 2, 3, 4], &[1, 2, 3, 1, 2, 3]]);
+
+        for format in [VocabFormat::Json, VocabFormat::Binary] {
+            let loaded = round_trip(&bpe, format);
+            assert_eq!(loaded.ids_to_tokens(), bpe.ids_to_tokens());
+            assert_eq!(loaded.tokens_to_ids(), bpe.tokens_to_ids());
+            assert_eq!(
+                loaded.encode(&[1, 2, 3, 2, 3, 4]),
+                vec![TokenId(257), TokenId(256), TokenId(4)]
+            );
+        }
+
+        let config = BpeConfig::new().boundaries(Boundaries::Positions(vec![3, 70000]));
+        let bpe = Bpe::with_config(&[&[1, 2, 3, 1, 2, 3]], &config);
+
+        for format in [VocabFormat::Json, VocabFormat::Binary] {
+            let loaded = round_trip(&bpe, format);
+            assert_eq!(loaded.boundaries(), bpe.boundaries());
+            assert_eq!(
+                loaded.encode(&[1, 2, 3, 1, 2, 3]),
+                bpe.encode(&[1, 2, 3, 1, 2, 3])
+            );
+        }
+    }
+
+    #[test]
+    fn test_runs() {
+        let data = [b"abc".as_slice(), &[0; 40], b"abc", &[0; 20]]
+            .concat()
+            .repeat(2);
+        let config = BpeConfig::new().min_run_len(8);
+        let bpe = Bpe::with_config(&[&data], &config);
+        let runs = bpe.ids_to_tokens().values();
+        assert_eq!(
+            runs.filter(|token| matches!(token, Token::Run(..))).count(),
+            2
+        );
+
+        for format in [VocabFormat::Json, VocabFormat::Binary] {
+            let loaded = round_trip(&bpe, format);
+            assert_eq!(loaded.ids_to_tokens(), bpe.ids_to_tokens());
+            assert_eq!(loaded.min_run_len(), Some(8));
+            assert_eq!(loaded.encode(&data), bpe.encode(&data));
+        }
+        assert_eq!(
+            round_trip(&Bpe::new(&[&data]), VocabFormat::Binary).min_run_len(),
+            None
+        );
+
+        let mut tokens = (0..=u8::MAX).map(Token::Symbol).collect::<Vec<_>>();
+        tokens.push(Token::Run(TokenId(0), 8));
+        assert!(validate(&tokens).is_ok());
+        for bad in [
+            Token::Run(TokenId(0), 8),
+            Token::Run(TokenId(0), 1),
+            Token::Run(TokenId(256), 8),
+            Token::Run(TokenId(300), 8),
+            Token::Run(TokenId(0), MAX_RUN_LEN + 1),
+            Token::Run(TokenId(0), usize::MAX),
+        ] {
+            let mut tokens = tokens.clone();
+            tokens.push(bad);
+            assert!(validate(&tokens).is_err());
+        }
+    }
+
+    #[test]
+    fn test_read_version_1() {
+        let mut tokens = (0..=u8::MAX).map(Token::Symbol).collect::<Vec<_>>();
+        tokens.push(Token::Merge(TokenId(1), TokenId(2)));
+
+        let json = serde_json::json!({"version": 1, "tokens": tokens})
+            .to_string()
+            .replace("Symbol", "Byte");
+        let bpe: Bpe = read(json.as_bytes()).unwrap();
+        assert_eq!(bpe.boundaries(), &Boundaries::None);
+        assert_eq!(bpe.encode(&[1, 2]), vec![TokenId(256)]);
+
+        let mut binary = MAGIC.to_vec();
+        binary.push(1);
+        write_varint(&mut binary, tokens.len() as u64);
+        for token in &tokens {
+            match *token {
+                Token::Symbol(b) => binary.extend([TAG_SYMBOL, b]),
+                Token::Merge(id0, id1) => binary.extend([TAG_MERGE, id0.0 as u8, id1.0 as u8]),
+                Token::Run(..) => unreachable!(),
+            }
+        }
+        let bpe: Bpe = read(binary.as_slice()).unwrap();
+        assert_eq!(bpe.encode(&[1, 2]), vec![TokenId(256)]);
+
+        // byte vocabularies only
+        assert!(read::<u16>(json.as_bytes()).is_err());
+        assert!(read::<u16>(binary.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let words: &[u16] = &[0x1234, 0xbeef, 0x1234, 0xbeef, 7];
+        let bpe = Bpe::from_symbols(&[words], &BpeConfig::new());
+        let text = "héllo héllo".chars().collect::<Vec<_>>();
+        let chars = Bpe::from_symbols(&[&text], &BpeConfig::new());
+
+        for format in [VocabFormat::Json, VocabFormat::Binary] {
+            let mut out = vec![];
+            write(&bpe, &mut out, format).unwrap();
+            let loaded: Bpe<u16> = read(out.as_slice()).unwrap();
+            assert_eq!(loaded.ids_to_tokens(), bpe.ids_to_tokens());
+            assert!(matches!(
+                read::<u8>(out.as_slice()),
+                Err(Error::InvalidVocabulary(_))
+            ));
+            assert!(read::<u32>(out.as_slice()).is_err());
+
+            let mut out = vec![];
+            write(&chars, &mut out, format).unwrap();
+            let loaded: Bpe<char> = read(out.as_slice()).unwrap();
+            assert_eq!(loaded.encode(&text), chars.encode(&text));
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let bytes = (0..=u8::MAX).map(Token::Symbol).collect::<Vec<_>>();
+        assert!(validate(&bytes).is_ok());
+
+        let mut tokens = bytes.clone();
+        tokens.push(Token::Merge(TokenId(1), TokenId(2)));
+        assert!(validate(&tokens).is_ok());
+
+        tokens.push(Token::Merge(TokenId(1), TokenId(2)));
+        assert!(validate(&tokens).is_err());
+
+        let mut tokens = bytes.clone();
+        tokens.push(Token::Merge(TokenId(1), TokenId(256)));
+        assert!(validate(&tokens).is_err());
+
+        let mut tokens = bytes.clone();
+        tokens.pop();
+        assert!(validate(&tokens).is_err());
+
+        let mut tokens = bytes.clone();
+        tokens[255] = Token::Symbol(0);
+        assert!(validate(&tokens).is_err());
+    }
+
+    #[test]
+    fn test_read_errors() {
+        assert!(read::<u8>(&b"{\"version\":5,\"tokens\":[]}"[..]).is_err());
+        assert!(read::<u8>(&b"not a vocabulary"[..]).is_err());
+
+        let bpe = Bpe::new(&[]);
+        let mut out = vec![];
+        write(&bpe, &mut out, VocabFormat::Binary).unwrap();
+
+        assert!(read::<u8>(&out[..out.len() - 1]).is_err());
+        out.push(0);
+        assert!(read::<u8>(out.as_slice()).is_err());
+    }
+}
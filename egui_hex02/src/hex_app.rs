@@ -1,5 +1,12 @@
 use crate::diff::{self, HexCell};
-use arb_comp05::{bpe::Bpe, matcher, test_utils};
+use arb_comp05::{
+    bpe::{Bpe, EncodeMode},
+    config::{BpeConfig, Weighting},
+    matcher,
+    pretokenize::Boundaries,
+    test_utils,
+    tokenizer::{BpeTokenizer, FixedChunks, GearChunks, Tokenizer},
+};
 use egui::{Color32, RichText, Ui};
 use egui_extras::{Column, TableBody, TableBuilder, TableRow};
 use rand::Rng;
@@ -8,6 +15,7 @@ use rand::Rng;
 enum WhichFile {
     File0,
     File1,
+    Vocabulary,
 }
 fn drop_select_text(selected: bool) -> &'static str {
     if selected {
@@ -21,6 +29,8 @@ fn drop_select_text(selected: bool) -> &'static str {
 enum DiffMethod {
     ByIndex,
     BpeGreedy00,
+    FixedChunks,
+    GearChunks,
 }
 
 pub struct HexApp {
@@ -32,6 +42,65 @@ pub struct HexApp {
     diffs1: Vec<HexCell>,
     file_drop_target: WhichFile,
     diff_method: DiffMethod,
+    /// Chunk length for the chunking diff methods (the average for `GearChunks`)
+    chunk_len: usize,
+    bpe_config: BpeConfig,
+    encode_mode: EncodeMode,
+    /// A vocabulary used for every diff instead of training on the current pair, and its name
+    vocabulary: Option<(String, Bpe)>,
+    vocabulary_error: Option<String>,
+}
+
+/// Edits an optional limit with a checkbox and a value; returns true when the value is settled.
+fn limit_edit(ui: &mut Ui, label: &str, limit: &mut Option<usize>, default: usize) -> bool {
+    let mut enabled = limit.is_some();
+    let mut changed = ui.checkbox(&mut enabled, label).changed();
+    if changed {
+        *limit = enabled.then_some(limit.unwrap_or(default));
+    }
+
+    if let Some(value) = limit {
+        let response = ui.add(egui::DragValue::new(value).clamp_range(1..=usize::MAX));
+        changed |= response.drag_stopped() || (response.changed() && !response.dragged());
+    }
+    changed
+}
+
+/// Selects a boundary rule; returns true when the rule is settled.
+fn boundaries_edit(ui: &mut Ui, boundaries: &mut Boundaries) -> bool {
+    let mut changed = false;
+    let text = match boundaries {
+        Boundaries::None => "none",
+        Boundaries::Positions(_) => "fixed positions",
+        Boundaries::EveryN(_) => "every N bytes",
+        Boundaries::Newlines => "newlines",
+        Boundaries::ZeroRuns => "0x00 runs",
+    };
+
+    ui.label("boundaries:");
+    egui::ComboBox::from_id_source("boundaries")
+        .selected_text(text)
+        .show_ui(ui, |ui| {
+            let options = [
+                ("none", Boundaries::None),
+                ("every N bytes", Boundaries::EveryN(16)),
+                ("newlines", Boundaries::Newlines),
+                ("0x00 runs", Boundaries::ZeroRuns),
+            ];
+            for (text, value) in options {
+                let selected = std::mem::discriminant(boundaries) == std::mem::discriminant(&value);
+                if ui.selectable_label(selected, text).clicked() && !selected {
+                    *boundaries = value;
+                    changed = true;
+                }
+            }
+        });
+
+    if let Boundaries::EveryN(n) = boundaries {
+        let response = ui.add(egui::DragValue::new(n).clamp_range(1..=usize::MAX));
+        changed |= response.drag_stopped() || (response.changed() && !response.dragged());
+    }
+    changed
 }
 
 fn random_pattern() -> Vec<u8> {
@@ -50,6 +119,12 @@ impl HexApp {
             diffs1: vec![],
             file_drop_target: WhichFile::File0,
             diff_method: DiffMethod::ByIndex,
+            chunk_len: 16,
+            // the default inputs are zero buffers: give their runs single tokens
+            bpe_config: BpeConfig::default().min_run_len(16),
+            encode_mode: EncodeMode::Greedy,
+            vocabulary: None,
+            vocabulary_error: None,
         };
 
         result.update_diffs();
@@ -60,16 +135,34 @@ impl HexApp {
         let (diffs1, diffs2) =
             if let (Some(pattern0), Some(pattern1)) = (&self.pattern0, &self.pattern1) {
                 let len = std::cmp::max(pattern0.len(), pattern1.len());
-                match self.diff_method {
-                    DiffMethod::ByIndex => diff::get_diffs(pattern0, pattern1, 0..len),
+                let trained;
+                let tokenizer: Option<Box<dyn Tokenizer>> = match self.diff_method {
+                    DiffMethod::ByIndex => None,
                     DiffMethod::BpeGreedy00 => {
-                        let bpe = Bpe::new(&[pattern0, pattern1]);
+                        let bpe = match &self.vocabulary {
+                            Some((_, bpe)) => bpe,
+                            None => {
+                                trained = Bpe::with_config(&[pattern0, pattern1], &self.bpe_config);
+                                &trained
+                            }
+                        };
+                        Some(Box::new(BpeTokenizer {
+                            bpe,
+                            mode: self.encode_mode,
+                        }))
+                    }
+                    DiffMethod::FixedChunks => Some(Box::new(FixedChunks::new(self.chunk_len))),
+                    DiffMethod::GearChunks => Some(Box::new(GearChunks::new(self.chunk_len))),
+                };
 
-                        let pattern0 = bpe.encode(pattern0);
-                        let pattern1 = bpe.encode(pattern1);
+                match tokenizer {
+                    None => diff::get_diffs(pattern0, pattern1, 0..len),
+                    Some(mut tokenizer) => {
+                        let pattern0 = tokenizer.encode(pattern0);
+                        let pattern1 = tokenizer.encode(pattern1);
 
                         let matches = matcher::greedy00(&pattern0, &pattern1);
-                        test_utils::matches_to_cells(&matches, |x| bpe.decode(x.clone()))
+                        test_utils::matches_to_cells(&matches, |x| tokenizer.decode(x))
                     }
                 }
             } else {
@@ -79,11 +172,117 @@ impl HexApp {
         self.diffs1 = diffs2;
     }
 
+    fn add_vocabulary_row(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+
+        match &mut self.vocabulary {
+            Some((name, bpe)) => {
+                ui.label(format!(
+                    "vocabulary: {name} ({} tokens)",
+                    bpe.tokens().len()
+                ));
+                if let (Some(pattern0), Some(pattern1)) = (&self.pattern0, &self.pattern1) {
+                    if ui.button("extend with current files").clicked() {
+                        bpe.extend_with_config(&[pattern0, pattern1], &self.bpe_config);
+                        changed = true;
+                    }
+                }
+                if ui.button("train on current files").clicked() {
+                    self.vocabulary = None;
+                    changed = true;
+                }
+            }
+            None => {
+                ui.label("vocabulary: trained on current files");
+                if let (Some(pattern0), Some(pattern1)) = (&self.pattern0, &self.pattern1) {
+                    if ui.button("keep this vocabulary").clicked() {
+                        let bpe = Bpe::with_config(&[pattern0, pattern1], &self.bpe_config);
+                        let name = format!(
+                            "{} + {}",
+                            self.source_name0.as_deref().unwrap_or("?"),
+                            self.source_name1.as_deref().unwrap_or("?")
+                        );
+                        self.vocabulary = Some((name, bpe));
+                    }
+                }
+            }
+        }
+
+        let text = if self.file_drop_target == WhichFile::Vocabulary {
+            "⬇ Loading dropped vocabulary ⬇"
+        } else {
+            "⬇ Load dropped vocabulary ⬇"
+        };
+        ui.selectable_value(&mut self.file_drop_target, WhichFile::Vocabulary, text)
+            .highlight();
+
+        if let Some(error) = &self.vocabulary_error {
+            ui.colored_label(Color32::RED, error);
+        }
+
+        if changed {
+            self.update_diffs();
+        }
+    }
+
+    fn add_bpe_config_row(&mut self, ui: &mut Ui) {
+        let config = &mut self.bpe_config;
+        let mut changed = false;
+
+        if self.vocabulary.is_none() {
+            ui.label("BPE limits:");
+            changed |= limit_edit(ui, "max vocab size", &mut config.max_vocab_size, 4096);
+            changed |= limit_edit(ui, "max token length", &mut config.max_token_len, 64);
+            changed |= limit_edit(ui, "max merges", &mut config.max_merges, 1000);
+            changed |= limit_edit(ui, "run tokens from length", &mut config.min_run_len, 16);
+
+            ui.label("min pair frequency");
+            let response = ui.add(
+                egui::DragValue::new(&mut config.min_pair_frequency).clamp_range(2..=usize::MAX),
+            );
+            changed |= response.drag_stopped() || (response.changed() && !response.dragged());
+
+            // both files have to share a pair for it to be merged
+            let mut shared = config.min_inputs > 1;
+            if ui.checkbox(&mut shared, "shared merges only").changed() {
+                config.min_inputs = if shared { 2 } else { 1 };
+                changed = true;
+            }
+
+            // a small file would otherwise contribute few merges next to a large one
+            let mut per_input = config.weighting == Weighting::PerInput;
+            if ui.checkbox(&mut per_input, "weigh files equally").changed() {
+                config.weighting = if per_input {
+                    Weighting::PerInput
+                } else {
+                    Weighting::Occurrences
+                };
+                changed = true;
+            }
+
+            changed |= boundaries_edit(ui, &mut config.boundaries);
+        }
+
+        let mut ranked = self.encode_mode == EncodeMode::Ranked;
+        if ui.checkbox(&mut ranked, "rank-ordered encoding").changed() {
+            self.encode_mode = if ranked {
+                EncodeMode::Ranked
+            } else {
+                EncodeMode::Greedy
+            };
+            changed = true;
+        }
+
+        if changed {
+            self.update_diffs();
+        }
+    }
+
     fn add_header_row(&mut self, mut header: TableRow<'_, '_>) {
         let no_pattern = "[none]".to_string();
 
         header.col(|ui| {
-            ui.heading("address");
+            ui.heading("addresses");
         });
         header.col(|ui| {
             ui.heading(self.source_name0.as_ref().unwrap_or(&no_pattern));
@@ -145,12 +344,16 @@ impl HexApp {
                     let cell = diffs.get(i + row_index * hex_grid_width);
 
                     match cell {
-                        Some(&HexCell::Same { value, source_id }) => ui.label(
+                        Some(&HexCell::Same {
+                            value, source_id, ..
+                        }) => ui.label(
                             RichText::new(format!("{value:02X}"))
                                 .color(color(source_id))
                                 .monospace(),
                         ),
-                        Some(&HexCell::Diff { value, source_id }) => {
+                        Some(&HexCell::Diff {
+                            value, source_id, ..
+                        }) => {
                             let color = color(source_id);
                             let contrast = contrast(color);
                             ui.label(
@@ -172,12 +375,16 @@ impl HexApp {
                     let cell = diffs.get(i + row_index * hex_grid_width);
 
                     match cell {
-                        Some(&HexCell::Same { value, source_id }) => ui.label(
+                        Some(&HexCell::Same {
+                            value, source_id, ..
+                        }) => ui.label(
                             RichText::new(format!("{}", value as char))
                                 .color(color(source_id))
                                 .monospace(),
                         ),
-                        Some(&HexCell::Diff { value, source_id }) => {
+                        Some(&HexCell::Diff {
+                            value, source_id, ..
+                        }) => {
                             let color = color(source_id);
                             let contrast = contrast(color);
 
@@ -194,8 +401,18 @@ impl HexApp {
                 });
             };
 
+            // the offsets in each file of the row's first bytes, which differ once blanks align them
+            let address = |diffs: &Vec<HexCell>| {
+                let row = diffs.iter().skip(row_index * hex_grid_width);
+                match row.take(hex_grid_width).find_map(HexCell::offset) {
+                    Some(offset) => format!("{offset:08X}"),
+                    None => "--------".to_string(),
+                }
+            };
+
             row.col(|ui| {
-                ui.label(RichText::new(format!("{:08X}", row_index * hex_grid_width)).monospace());
+                let text = format!("{} {}", address(&self.diffs0), address(&self.diffs1));
+                ui.label(RichText::new(text).monospace());
             });
             row.col(|ui| add_hex_row(ui, &self.diffs0));
             row.col(|ui| add_ascii_row(ui, &self.diffs0));
@@ -219,6 +436,15 @@ impl eframe::App for HexApp {
                             self.pattern1 = Some(bytes.to_vec());
                             self.source_name1 = Some(dropped_file.name.clone());
                         }
+                        WhichFile::Vocabulary => match Bpe::read_from(&bytes[..]) {
+                            Ok(bpe) => {
+                                self.vocabulary = Some((dropped_file.name.clone(), bpe));
+                                self.vocabulary_error = None;
+                            }
+                            Err(e) => {
+                                self.vocabulary_error = Some(format!("{}: {e}", dropped_file.name));
+                            }
+                        },
                     }
                     self.update_diffs();
                 }
@@ -244,8 +470,40 @@ impl eframe::App for HexApp {
                 {
                     self.update_diffs();
                 }
+
+                if ui
+                    .selectable_value(&mut self.diff_method, FixedChunks, "Fixed Chunks")
+                    .clicked()
+                {
+                    self.update_diffs();
+                }
+
+                if ui
+                    .selectable_value(&mut self.diff_method, GearChunks, "Gear CDC")
+                    .clicked()
+                {
+                    self.update_diffs();
+                }
             });
 
+            match self.diff_method {
+                DiffMethod::BpeGreedy00 => {
+                    ui.horizontal(|ui| self.add_bpe_config_row(ui));
+                    ui.horizontal(|ui| self.add_vocabulary_row(ui));
+                }
+                DiffMethod::FixedChunks | DiffMethod::GearChunks => {
+                    ui.horizontal(|ui| {
+                        ui.label("chunk length");
+                        let response =
+                            ui.add(egui::DragValue::new(&mut self.chunk_len).clamp_range(1..=4096));
+                        if response.drag_stopped() || (response.changed() && !response.dragged()) {
+                            self.update_diffs();
+                        }
+                    });
+                }
+                DiffMethod::ByIndex => {}
+            }
+
             TableBuilder::new(ui)
                 .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
                 .striped(true)